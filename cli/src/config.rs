@@ -1,14 +1,9 @@
-use std::path::PathBuf;
+use cephalon_rust_core::settings::EngineSettings;
 
 use tokio::sync::OnceCell;
 
-#[derive(serde::Deserialize)]
-pub struct Settings {
-    pub cache_path: PathBuf,
-}
-
-pub async fn settings() -> &'static Settings {
-    static ONCE: OnceCell<Settings> = OnceCell::const_new();
+pub async fn settings() -> &'static EngineSettings {
+    static ONCE: OnceCell<EngineSettings> = OnceCell::const_new();
 
     (ONCE
         .get_or_init(|| async {
@@ -1,12 +1,27 @@
 pub mod config;
 
-use std::{fs::OpenOptions, path::Path};
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use anyhow::{anyhow, bail};
 use cephalon_rust_core::{
+    config::MarketClient,
     event::{Event, RewardSlot},
-    Engine,
+    items::{
+        cached_fetch_relics, cached_get_item_identifiers,
+        relic_value::{drop_prices, rank_relics, relic_values, Refinement},
+        snapshot::Snapshot,
+    },
+    log_watcher::replay::Replay,
+    settings::EngineSettings,
+    valuation::BestUse,
+    Engine, ImageCapture,
 };
 use config::settings;
+use indicatif::{ProgressBar, ProgressStyle};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter, Registry};
 
 #[tokio::main]
@@ -24,16 +39,55 @@ async fn main() -> anyhow::Result<()> {
         .with(fmt::layer().with_writer(log_file));
     tracing::subscriber::set_global_default(subscriber).unwrap();
     let setting = settings().await;
-    let engine = Engine::new(Path::new(&setting.cache_path).to_path_buf()).await?;
-    println!("engine inited");
-
+    let market = setting.market();
+    let command = parse_command(std::env::args().skip(1))?;
+    if let Command::Snapshot(out) = &command {
+        return write_snapshot(setting, &market, out).await;
+    }
+    let prices = setting.price_cache(market.clone())?;
+    let valuation = setting.valuation().await?;
     let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(100);
 
     tokio::spawn(async move {
+        // only exists while a cold-cache item fetch is running
+        let mut progress_bar: Option<ProgressBar> = None;
         while let Some(event) = rx.recv().await {
             match event {
-                Event::RewardScreenOpened { count, window } => {
-                    println!("reward screen opened ({count} cards) window: {window:?}")
+                Event::ItemDatabaseProgress {
+                    phase,
+                    progress,
+                    retries,
+                } => {
+                    let bar = progress_bar.get_or_insert_with(|| {
+                        ProgressBar::new(0).with_style(
+                            ProgressStyle::with_template(
+                                "building item database: {prefix} [{bar:30}] {pos}/{len} {msg}",
+                            )
+                            .unwrap()
+                            .progress_chars("=> "),
+                        )
+                    });
+                    bar.set_prefix(phase.to_string());
+                    bar.set_length(progress.total as u64);
+                    bar.set_position(progress.done as u64);
+                    bar.set_message(format!("{} failed, {retries} retries", progress.failed));
+                }
+                // main reports the error itself
+                Event::ItemDatabaseReady | Event::ItemDatabaseFailed { .. } => {
+                    if let Some(bar) = progress_bar.take() {
+                        bar.finish_and_clear();
+                    }
+                }
+                Event::RewardScreenOpened {
+                    count,
+                    window,
+                    relics,
+                } => {
+                    println!("reward screen opened ({count} cards) window: {window:?}");
+                    for x in relics {
+                        let whose = x.player.unwrap_or_else(|| "you".to_string());
+                        println!("  {whose}: {} {}", x.relic.name, x.refinement);
+                    }
                 }
                 Event::RewardsResolved(slots) => {
                     let summary = slots
@@ -43,18 +97,216 @@ async fn main() -> anyhow::Result<()> {
                             RewardSlot::Forma => "forma".to_string(),
                             RewardSlot::Item {
                                 item,
-                                price: Some(p),
-                            } => format!("{} {p}p", item.name),
-                            RewardSlot::Item { item, price: None } => format!("{} ?p", item.name),
+                                price,
+                                set,
+                                ducats,
+                                best_use,
+                                ..
+                            } => {
+                                let mut text = format!("{} {} {ducats}d", item.name, plat(*price));
+                                if *best_use == Some(BestUse::Baro) {
+                                    text += " baro";
+                                }
+                                if let Some(set) = set {
+                                    text += &format!(
+                                        " (set {}, parts {})",
+                                        plat(set.price),
+                                        plat(set.parts_price)
+                                    );
+                                }
+                                text
+                            }
                         })
                         .collect::<Vec<_>>()
                         .join(" | ");
                     println!("{summary}");
                 }
-                Event::RewardScreenClosed => println!("reward screen closed"),
+                Event::Recommendation { slot, reason } => {
+                    println!("pick card {}: {reason}", slot + 1)
+                }
+                Event::RewardPicked { slot: Some(slot) } => println!("picked card {}", slot + 1),
+                Event::RewardPicked { slot: None } => println!("reward picked"),
+                Event::RewardScreenClosed { reason } => println!("reward screen closed: {reason}"),
+                Event::GameRestarted => println!("game restarted"),
+                Event::GameStateChanged(change) => println!("{change}"),
             }
         }
     });
-    engine.run(tx).await;
+    let engine = Engine::new(
+        Path::new(&setting.cache_path).to_path_buf(),
+        &market,
+        Arc::new(prices),
+        valuation,
+        setting.snapshot().await.as_ref(),
+        setting.drop_data_path.as_deref(),
+        &tx,
+    )
+    .await?;
+    match command {
+        Command::Run => {
+            println!("engine inited");
+            engine.run(tx, setting.ee_log_path.clone()).await;
+        }
+        Command::Replay(replay, screenshot) => {
+            // the screenshot stands in for the game, if there is one
+            let engine = match screenshot {
+                Some(path) => engine.with_capture(ImageCapture::open(&path)?),
+                None => engine,
+            };
+            println!("replaying {}", replay.path.display());
+            engine.run_from(tx, replay).await;
+        }
+        Command::Snapshot(_) => unreachable!("handled before the engine starts"),
+        Command::RelicEv { relic, squad } => print_relic_ev(&engine, &relic, squad).await?,
+        Command::BestRelics { refinement, squad } => {
+            print_best_relics(&engine, refinement, squad).await?
+        }
+    }
+    Ok(())
+}
+
+const USAGE: &str = "usage: cephalon [snapshot <file> | relic-ev <relic> [squad] | best-relics [refinement] [squad] | replay <EE.log> [instant|<n>x] [screenshot]]";
+
+/// what the cli was asked to do; everything but `Run` exits when done
+enum Command {
+    Run,
+    Snapshot(PathBuf),
+    RelicEv { relic: String, squad: u32 },
+    BestRelics { refinement: Refinement, squad: u32 },
+    Replay(Replay, Option<PathBuf>),
+}
+
+fn parse_command(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let command = args.next();
+    let mut arg = || args.next();
+    let squad = |x: Option<String>| -> anyhow::Result<u32> {
+        match x {
+            Some(x) => Ok(x
+                .parse()
+                .map_err(|_| anyhow!("squad size {x:?} isn't a number"))?),
+            None => Ok(4),
+        }
+    };
+    Ok(match command.as_deref() {
+        None => Command::Run,
+        Some("snapshot") => Command::Snapshot(arg().ok_or_else(|| anyhow!(USAGE))?.into()),
+        Some("relic-ev") => Command::RelicEv {
+            relic: arg().ok_or_else(|| anyhow!(USAGE))?,
+            squad: squad(arg())?,
+        },
+        Some("best-relics") => Command::BestRelics {
+            refinement: arg()
+                .map(|x| x.parse())
+                .transpose()?
+                .unwrap_or(Refinement::Radiant),
+            squad: squad(arg())?,
+        },
+        Some("replay") => Command::Replay(
+            Replay {
+                path: arg().ok_or_else(|| anyhow!(USAGE))?.into(),
+                speed: arg().map(|x| x.parse()).transpose()?.unwrap_or_default(),
+            },
+            arg().map(PathBuf::from),
+        ),
+        Some(command) => bail!("unknown command {command:?}\n{USAGE}"),
+    })
+}
+
+/// `cephalon relic-ev <relic> [squad]`: expected value of one relic at every
+/// refinement, for a radshare of `squad`
+async fn print_relic_ev(engine: &Engine, query: &str, squad: u32) -> anyhow::Result<()> {
+    let db = engine.database();
+    let relic = db
+        .relic_by_name(query)
+        .ok_or_else(|| anyhow!("no relic called {query:?}"))?;
+    let table = db
+        .drop_tables
+        .for_relic(&relic.id)
+        .ok_or_else(|| anyhow!("no drop table for {}, is DROP_DATA_PATH set?", relic.name))?;
+    let part_prices = drop_prices([table], &db, engine.prices()).await;
+    let values = relic_values(table, &db, &part_prices, squad)
+        .ok_or_else(|| anyhow!("couldn't price every part of {}", relic.name))?;
+    println!("{}, squad of {squad}", relic.name);
+    for (rarity, item) in table.items(&db) {
+        let price = part_prices.get(&item.id).copied();
+        println!(
+            "  {rarity:?} {} {} {}d",
+            item.name,
+            plat(price),
+            item.ducats
+        );
+    }
+    for value in values {
+        println!(
+            "{:<12} {:>7.1}p {:>7.1}d",
+            value.refinement.to_string(),
+            value.plat,
+            value.ducats
+        );
+    }
+    Ok(())
+}
+
+/// `cephalon best-relics [refinement] [squad]`: every relic with a drop
+/// table by expected platinum, best first
+async fn print_best_relics(
+    engine: &Engine,
+    refinement: Refinement,
+    squad: u32,
+) -> anyhow::Result<()> {
+    let db = engine.database();
+    if db.drop_tables.is_empty() {
+        bail!("no drop tables, is DROP_DATA_PATH set?");
+    }
+    println!("{refinement}, squad of {squad}");
+    for (rank, (relic, value)) in rank_relics(&db, engine.prices(), refinement, squad)
+        .await
+        .into_iter()
+        .take(BEST_RELICS_SHOWN)
+        .enumerate()
+    {
+        println!(
+            "{:>3}. {:<24} {:>7.1}p {:>7.1}d{}",
+            rank + 1,
+            relic.name,
+            value.plat,
+            value.ducats,
+            if relic.vaulted { "  (vaulted)" } else { "" }
+        );
+    }
     Ok(())
 }
+
+const BEST_RELICS_SHOWN: usize = 25;
+
+/// `cephalon snapshot <file>`: dump the item cache into a snapshot that can be
+/// shipped to machines without a warm cache (see `SNAPSHOT_PATH`). relics are
+/// fetched if they were never cached
+async fn write_snapshot(
+    setting: &EngineSettings,
+    market: &MarketClient,
+    out: &Path,
+) -> anyhow::Result<()> {
+    let cache_path = Path::new(&setting.cache_path);
+    let identifiers = cached_get_item_identifiers(cache_path, market, &|_| {})
+        .await?
+        .data;
+    cached_fetch_relics(cache_path, market, &identifiers, &|_| {}).await?;
+    let snapshot = Snapshot::from_cache(cache_path).await?;
+    snapshot.write(out).await?;
+    println!(
+        "wrote {} items, {} sets and {} relics from {} to {out:?}",
+        snapshot.items.len(),
+        snapshot.sets.len(),
+        snapshot.relics.len(),
+        snapshot.created_at
+    );
+    Ok(())
+}
+
+fn plat(price: Option<u32>) -> String {
+    match price {
+        Some(p) => format!("{p}p"),
+        None => "?p".to_string(),
+    }
+}
@@ -1,17 +1,127 @@
-use std::time::Duration;
-
-use tokio::{
-    sync::{OnceCell, Semaphore},
-    time::sleep,
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
 };
 
-use http::{Extensions, StatusCode};
+use tokio::time::{sleep, Instant};
+
+use http::{header::RETRY_AFTER, Extensions, StatusCode};
 use reqwest::{Client, Request, Response};
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Middleware, Next, Result};
+use reqwest_middleware::{
+    ClientBuilder, ClientWithMiddleware, Middleware, Next, RequestBuilder, Result,
+};
+use serde::de::DeserializeOwned;
+use tracing::*;
+
+use crate::items::{Data, ReqwestSerdeError};
 
-static PERMITS: Semaphore = Semaphore::const_new(10);
+/// the live api; paths like `/v2/items` are appended to it
+pub const DEFAULT_MARKET_URL: &str = "https://api.warframe.market";
+/// warframe.market's documented limit is 3 requests per second
+pub const MARKET_REQUESTS_PER_SECOND: f64 = 3.0;
+/// retries after the first attempt before giving up with [`RetriesExhausted`]
+const MAX_RETRIES: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// upper bound on a server-provided `Retry-After`, so a bogus header can't
+/// park a request for hours
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
 
-struct RateLimittingMiddleware;
+/// Returned (wrapped in `reqwest_middleware::Error::Middleware`) when a request
+/// kept failing transiently for every allowed attempt.
+#[derive(thiserror::Error, Debug)]
+pub enum RetriesExhausted {
+    #[error("{url} still answered {status} after {attempts} attempts")]
+    Status {
+        url: String,
+        status: StatusCode,
+        attempts: u32,
+    },
+    #[error("{url} failed after {attempts} attempts")]
+    Transport {
+        url: String,
+        attempts: u32,
+        #[source]
+        source: reqwest_middleware::Error,
+    },
+}
+
+/// Token bucket shared by every request the client makes. Tokens refill at
+/// `rate` per second up to `burst`; a caller that finds the bucket empty
+/// reserves the next token (driving the count negative) and sleeps until it
+/// would exist, so waiters are served in arrival order.
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    async fn acquire(&self) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens + (now - *last).as_secs_f64() * self.rate).min(self.burst);
+            *last = now;
+            *tokens -= 1.0;
+            (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / self.rate))
+        };
+        if let Some(wait) = wait {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// exponential backoff for the given retry (1-based) with "equal jitter":
+/// half the delay is fixed, the other half random
+fn backoff(retry: u32) -> Duration {
+    let exp = BASE_BACKOFF
+        .saturating_mul(1 << retry.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF);
+    exp / 2 + (exp / 2).mul_f64(fastrand::f64())
+}
+
+/// `Retry-After` is either delay-seconds or an HTTP date
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .unwrap_or_default(),
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn is_retryable_error(e: &reqwest_middleware::Error) -> bool {
+    match e {
+        reqwest_middleware::Error::Reqwest(e) => e.is_connect() || e.is_timeout(),
+        reqwest_middleware::Error::Middleware(_) => false,
+    }
+}
+
+struct RateLimittingMiddleware {
+    bucket: TokenBucket,
+    /// shared with [`MarketClient::retries`]
+    retries: Arc<AtomicU64>,
+}
 
 #[async_trait::async_trait]
 impl Middleware for RateLimittingMiddleware {
@@ -21,32 +131,171 @@ impl Middleware for RateLimittingMiddleware {
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> Result<Response> {
-        let permit = PERMITS.acquire().await.unwrap();
-        let res = loop {
-            let res = next
-                .clone()
-                .run(req.try_clone().unwrap(), extensions)
-                .await?;
-            if res.status() != StatusCode::TOO_MANY_REQUESTS {
-                break res;
+        let url = req.url().to_string();
+        let mut retries = 0;
+        loop {
+            self.bucket.acquire().await;
+            // streaming bodies can't be cloned, so they get exactly one attempt
+            let Some(attempt) = req.try_clone() else {
+                return next.run(req, extensions).await;
+            };
+            let res = next.clone().run(attempt, extensions).await;
+            let delay = match &res {
+                Ok(r) if is_retryable_status(r.status()) => {
+                    retry_after(r).unwrap_or_else(|| backoff(retries + 1))
+                }
+                Err(e) if is_retryable_error(e) => backoff(retries + 1),
+                _ => return res,
+            };
+            if retries == MAX_RETRIES {
+                let attempts = retries + 1;
+                let exhausted = match res {
+                    Ok(r) => RetriesExhausted::Status {
+                        url,
+                        status: r.status(),
+                        attempts,
+                    },
+                    Err(source) => RetriesExhausted::Transport {
+                        url,
+                        attempts,
+                        source,
+                    },
+                };
+                event!(Level::WARN, "{exhausted}");
+                return Err(reqwest_middleware::Error::middleware(exhausted));
             }
-            sleep(Duration::new(0, 100)).await;
-        };
-        drop(permit);
-        Ok(res)
+            retries += 1;
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            match &res {
+                Ok(r) => event!(
+                    Level::INFO,
+                    "{url} answered {}, retry {retries} in {delay:?}",
+                    r.status()
+                ),
+                Err(e) => event!(
+                    Level::INFO,
+                    "{url} failed ({e}), retry {retries} in {delay:?}"
+                ),
+            }
+            sleep(delay).await;
+        }
     }
 }
 
-pub async fn client() -> &'static ClientWithMiddleware {
-    static ONCE: OnceCell<ClientWithMiddleware> = OnceCell::const_new();
+/// HTTP client for one warframe.market-compatible api: the live site by
+/// default, or a local stand-in for tests. Cloning is cheap and clones share
+/// the rate limiter.
+#[derive(Clone)]
+pub struct MarketClient {
+    http: ClientWithMiddleware,
+    base_url: String,
+    retries: Arc<AtomicU64>,
+}
+
+impl MarketClient {
+    /// `base_url` without a trailing slash, e.g. `http://127.0.0.1:4000`
+    pub fn new(base_url: impl Into<String>, requests_per_second: f64) -> Self {
+        let reqwest_client = Client::builder().build().unwrap();
+        let retries = Arc::new(AtomicU64::new(0));
+        Self {
+            http: ClientBuilder::new(reqwest_client)
+                .with(RateLimittingMiddleware {
+                    bucket: TokenBucket::new(requests_per_second, requests_per_second),
+                    retries: retries.clone(),
+                })
+                .build(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            retries,
+        }
+    }
 
-    (ONCE
-        .get_or_init(|| async {
-            let reqwest_client = Client::builder().build().unwrap();
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// requests retried so far (429, 5xx or connection errors), over the
+    /// lifetime of this client and its clones
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// GET `path` (starting with `/`) relative to the base url
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.http.get(format!("{}{path}", self.base_url))
+    }
+
+    /// GET a v2 endpoint, its `data` along with the `apiVersion` it came with
+    pub async fn get_data<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> std::result::Result<Data<T>, ReqwestSerdeError> {
+        Ok(self.get(path).send().await?.json::<Data<T>>().await?)
+    }
+}
 
-            ClientBuilder::new(reqwest_client)
-                .with(RateLimittingMiddleware)
-                .build()
-        })
-        .await) as _
+impl Default for MarketClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_MARKET_URL, MARKET_REQUESTS_PER_SECOND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket_paces_after_burst() {
+        let bucket = TokenBucket::new(3.0, 3.0);
+        let start = Instant::now();
+        for _ in 0..3 {
+            bucket.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        for _ in 0..6 {
+            bucket.acquire().await;
+        }
+        // 6 more tokens at 3/s
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(1990) && elapsed <= Duration::from_millis(2010),
+            "{elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let within = |retry, min, max| {
+            // the jitter is random, give it a few goes
+            for _ in 0..50 {
+                let delay = backoff(retry);
+                assert!(
+                    delay >= Duration::from_millis(min) && delay <= Duration::from_millis(max),
+                    "{retry}: {delay:?}"
+                );
+            }
+        };
+        within(1, 250, 500);
+        within(3, 1_000, 2_000);
+        // 32s uncapped
+        within(7, 15_000, 30_000);
+        within(30, 15_000, 30_000);
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let response = |value: &str| {
+            Response::from(
+                http::Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header(RETRY_AFTER, value)
+                    .body("")
+                    .unwrap(),
+            )
+        };
+        assert_eq!(retry_after(&response("2")), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(&response("86400")), Some(MAX_RETRY_AFTER));
+        let past = httpdate::fmt_http_date(SystemTime::UNIX_EPOCH);
+        assert_eq!(retry_after(&response(&past)), Some(Duration::ZERO));
+        assert_eq!(retry_after(&response("soon")), None);
+    }
 }
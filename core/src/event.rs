@@ -1,4 +1,10 @@
-use crate::{geometry::WindowRect, items::items::Item};
+use crate::{
+    game_state::GameChange,
+    geometry::WindowRect,
+    items::{items::Item, relic_value::Refinement, relics::Relic, FetchProgress},
+    recommendation::Reason,
+    valuation::BestUse,
+};
 
 /// One reward card slot, indexed to match `geometry::reward_card_regions`.
 #[derive(Debug, Clone, PartialEq)]
@@ -8,15 +14,148 @@ pub enum RewardSlot {
     /// forma blueprint — has no market price
     Forma,
     /// identified item; price is None if the market lookup failed
-    Item { item: Item, price: Option<u32> },
+    Item {
+        item: Item,
+        price: Option<u32>,
+        /// None if the item's set isn't in the database, or until its prices
+        /// are in; a later `RewardsResolved` fills it in
+        set: Option<SetContext>,
+        /// what Baro Ki'Teer gives for it, 0 if he doesn't take it
+        ducats: u32,
+        /// `price` over `ducats`; None without a price or for 0 ducats
+        plat_per_ducat: Option<f64>,
+        /// selling against trading to Baro at the engine's `Valuation`;
+        /// None without a price
+        best_use: Option<BestUse>,
+    },
+}
+
+/// What the set a reward belongs to is worth, to decide between keeping the
+/// part for the set and selling it loose.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetContext {
+    pub name: String,
+    /// the full set's own market price
+    pub price: Option<u32>,
+    /// every part's price times its `quantity_for_set`, summed; None if any
+    /// of their lookups failed
+    pub parts_price: Option<u32>,
+    /// how many of this part the set needs
+    pub quantity_for_set: u32,
+}
+
+/// What the first-run item database build is fetching right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemDatabasePhase {
+    /// `/v2/items`, one request
+    ItemList,
+    /// one `/v2/item/{slug}/set` request per prime item
+    ItemSets,
+    /// one `/v2/item/{slug}` request per relic
+    Relics,
+}
+
+impl std::fmt::Display for ItemDatabasePhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ItemDatabasePhase::ItemList => write!(f, "item list"),
+            ItemDatabasePhase::ItemSets => write!(f, "item sets"),
+            ItemDatabasePhase::Relics => write!(f, "relics"),
+        }
+    }
+}
+
+/// A relic cracked for the reward screen, as the log said it was equipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EquippedRelic {
+    /// whose it is; None for the local player
+    pub player: Option<String>,
+    pub relic: Relic,
+    pub refinement: Refinement,
+}
+
+/// Why a reward screen's session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// the pick window ran out
+    TimedOut,
+    /// the log says the game closed the screen: the local player picked, or
+    /// the game's own timer ran out
+    ShutDown,
+    /// OCR can't find the cards on screen anymore, most likely picked
+    CardsGone,
+    /// the mission was aborted before anyone picked
+    Aborted,
+    /// the host left and someone else took over
+    HostMigration,
+    /// the local player lost the connection to the squad
+    Disconnected,
+    /// EE.log started over, see `GameRestarted`
+    GameRestarted,
+    /// the next reward screen opened before this one was seen closing
+    Replaced,
+}
+
+impl std::fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloseReason::TimedOut => write!(f, "pick window over"),
+            CloseReason::ShutDown => write!(f, "closed in game"),
+            CloseReason::CardsGone => write!(f, "cards gone"),
+            CloseReason::Aborted => write!(f, "mission aborted"),
+            CloseReason::HostMigration => write!(f, "host migration"),
+            CloseReason::Disconnected => write!(f, "disconnected"),
+            CloseReason::GameRestarted => write!(f, "game restarted"),
+            CloseReason::Replaced => write!(f, "next reward screen opened"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event {
+    /// `Engine::new` is fetching item data it had no cache for. Not sent at
+    /// all on a warm cache.
+    ItemDatabaseProgress {
+        phase: ItemDatabasePhase,
+        progress: FetchProgress,
+        /// market requests retried so far
+        retries: u64,
+    },
+    /// `Engine::new` is done; sent whether or not anything was fetched
+    ItemDatabaseReady,
+    /// `Engine::new` failed with `error`, no `ItemDatabaseReady` follows
+    ItemDatabaseFailed {
+        error: String,
+    },
     RewardScreenOpened {
         count: usize,
         window: Option<WindowRect>,
+        /// the relics the log said were equipped, resolved against the
+        /// item database; empty if it said nothing, or named unknown ones
+        relics: Vec<EquippedRelic>,
     },
     RewardsResolved(Vec<RewardSlot>),
-    RewardScreenClosed,
+    /// the slot to pick under the engine's objective, once OCR is done with
+    /// the screen; not sent if nothing on it is worth anything to it
+    Recommendation {
+        slot: usize,
+        reason: Reason,
+    },
+    /// the local player most likely picked: the log says the screen shut
+    /// down, or the cards went away. `slot` is the card selected on the last
+    /// capture that still showed them, None when it's not known which.
+    /// always followed by `RewardScreenClosed`
+    RewardPicked {
+        slot: Option<usize>,
+    },
+    RewardScreenClosed {
+        reason: CloseReason,
+    },
+    /// EE.log started over: the game was restarted, and whatever the engine
+    /// knew about the last session (squad size and the like) is reset. the
+    /// `GameStateChanged`s that follow say what that changed
+    GameRestarted,
+    /// something about the game changed, see
+    /// [`Engine::game_state`](crate::Engine::game_state) for all of it
+    GameStateChanged(GameChange),
 }
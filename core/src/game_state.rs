@@ -0,0 +1,546 @@
+use std::fmt::Display;
+
+use ctreg::regex;
+
+use crate::{
+    event::EquippedRelic,
+    items::{items::ItemDatabase, relic_value::Refinement},
+    log_watcher::{entry::System, LogEntry},
+};
+
+regex! { LoggedIn = r#"^Logged in (?<name>\S+) \((?<id>\w+)\)"# }
+regex! { SessionPlayers = r#"^Num session players: (?<count>\d+)$"# }
+regex! { LoadingLevel = r#"^Loading level (?<level>\S+)"# }
+regex! { MissionName = r#"^Mission name: (?<node>.+?)(?: - (?<kind>.+))?$"# }
+// `Tenno equipped Lith B4 Relic [Radiant]`, without a name for the local player.
+// a guess at the line, no real log with one in it yet
+regex! { RelicEquipped = r#"^(?:(?<player>\S+) )?equipped (?<relic>(?:Lith|Meso|Neo|Axi|Requiem) [A-Z0-9]+) Relic \[(?<refinement>\w+)\]$"# }
+
+/// `Sys [Info]` line once the level that was loading is up
+const LEVEL_LOADED: &str = "Level loaded";
+
+/// what the orbiter's level paths have in them
+const ORBITER_LEVELS: [&str; 2] = ["/Orbiter", "/PlayerShip"];
+
+/// the script that logs mission names and who's hosting them
+const SQUAD_OVERLAY: &str = "ThemedSquadOverlay";
+
+/// the script that logs who equipped which relic. like the line itself, a
+/// guess until a real log shows one
+const RELIC_SCRIPT: &str = "ProjectionManager";
+
+/// what a Script or Sys entry says, all of it, when the mission is aborted.
+/// menus mentioning aborting don't count. not checked against a real log yet
+const ABORT_LINES: [&str; 1] = ["Mission aborted"];
+
+/// what a Net entry says, all of it, when the local player loses the squad.
+/// not checked against a real log yet
+const DISCONNECT_LINES: [&str; 2] = ["Lost connection to host", "Disconnected from host"];
+
+/// the `Net [Info]` line a host migration starts with, once per migration;
+/// the lines about how it's going don't count. not checked against a real log
+/// yet either
+const HOST_MIGRATION_STARTED: &str = "Host migration started";
+
+/// relic eras, as they're named in fissure mission names. lowercase like
+/// [`Relic::era`](crate::items::relics::Relic::era)
+const ERAS: [&str; 6] = ["lith", "meso", "neo", "axi", "requiem", "omnia"];
+
+/// The mission being played, as the log names it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mission {
+    /// `Hepit (Void)`
+    pub node: String,
+    /// `Capture`, when the log says
+    pub kind: Option<String>,
+    /// the relic era of a void fissure, lowercase
+    pub era: Option<String>,
+}
+
+impl Mission {
+    fn from_log(node: &str, kind: Option<&str>) -> Self {
+        let text = format!("{node} {}", kind.unwrap_or_default()).to_lowercase();
+        let era = ERAS
+            .iter()
+            .find(|era| {
+                text.split(|x: char| !x.is_alphanumeric())
+                    .any(|word| word == **era)
+            })
+            .map(|x| x.to_string());
+        Self {
+            node: node.trim().to_string(),
+            kind: kind.map(|x| x.trim().to_string()),
+            era,
+        }
+    }
+}
+
+/// A relic someone brought to the fissure, as the log names it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedRelic {
+    /// whose it is; None for the local player
+    pub player: Option<String>,
+    /// `Lith B4`
+    pub name: String,
+    pub refinement: Refinement,
+}
+
+impl LoggedRelic {
+    /// the relic in `db` it names, None if there's no such relic
+    pub fn resolve(&self, db: &ItemDatabase) -> Option<EquippedRelic> {
+        Some(EquippedRelic {
+            player: self.player.clone(),
+            relic: db.relic_by_name(&self.name)?.clone(),
+            refinement: self.refinement,
+        })
+    }
+}
+
+/// Whether the local player hosts the mission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Host,
+    Client,
+}
+
+/// Where the local player is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Loading,
+    InMission,
+    Orbiter,
+}
+
+/// What a log entry changed about the [`GameState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameChange {
+    /// None once the game restarted
+    Account(Option<String>),
+    /// None once back on the orbiter
+    Mission(Option<Mission>),
+    SquadSize(usize),
+    /// None once back on the orbiter
+    Role(Option<Role>),
+    /// None once the game restarted
+    Phase(Option<Phase>),
+    /// the mission's host left and someone else took over
+    HostMigration,
+    /// the mission was aborted, everyone's headed back to the orbiter
+    MissionAborted,
+    /// the local player lost the connection to the squad
+    Disconnected,
+    /// someone equipped a relic for the mission
+    Relic(LoggedRelic),
+}
+
+impl Display for GameChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameChange::Account(Some(name)) => write!(f, "logged in as {name}"),
+            GameChange::Account(None) => write!(f, "logged out"),
+            GameChange::Mission(Some(mission)) => {
+                write!(f, "mission {}", mission.node)?;
+                if let Some(kind) = &mission.kind {
+                    write!(f, " ({kind})")?;
+                }
+                match &mission.era {
+                    Some(era) => write!(f, ", {era} fissure"),
+                    None => Ok(()),
+                }
+            }
+            GameChange::Mission(None) => write!(f, "mission over"),
+            GameChange::SquadSize(size) => write!(f, "squad of {size}"),
+            GameChange::Role(Some(Role::Host)) => write!(f, "hosting"),
+            GameChange::Role(Some(Role::Client)) => write!(f, "joined as a client"),
+            GameChange::Role(None) => write!(f, "left the squad"),
+            GameChange::Phase(Some(Phase::Loading)) => write!(f, "loading"),
+            GameChange::Phase(Some(Phase::InMission)) => write!(f, "in mission"),
+            GameChange::Phase(Some(Phase::Orbiter)) => write!(f, "on the orbiter"),
+            GameChange::Phase(None) => write!(f, "not in the game yet"),
+            GameChange::HostMigration => write!(f, "host migration"),
+            GameChange::MissionAborted => write!(f, "mission aborted"),
+            GameChange::Disconnected => write!(f, "disconnected"),
+            GameChange::Relic(relic) => {
+                match &relic.player {
+                    Some(player) => write!(f, "{player} brought ")?,
+                    None => write!(f, "brought ")?,
+                }
+                write!(f, "{} {}", relic.name, relic.refinement)
+            }
+        }
+    }
+}
+
+/// What EE.log has said about the game so far. Everything not logged yet is
+/// None, except the squad size, which is assumed full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameState {
+    /// the logged in account's name
+    pub account: Option<String>,
+    pub mission: Option<Mission>,
+    pub squad_size: usize,
+    pub role: Option<Role>,
+    pub phase: Option<Phase>,
+    /// the level loading or loaded last, `/Lotus/Levels/...`
+    pub level: Option<String>,
+    /// host migrations during the current mission
+    pub host_migrations: u32,
+    /// the relics equipped for the mission about to start or running, the
+    /// local player's and any squadmate's that was logged. picked on the
+    /// orbiter, so they're only forgotten once back on it
+    pub relics: Vec<LoggedRelic>,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self {
+            account: None,
+            mission: None,
+            squad_size: 4,
+            role: None,
+            phase: None,
+            level: None,
+            host_migrations: 0,
+            relics: Vec::new(),
+        }
+    }
+}
+
+impl GameState {
+    /// Fold `entry` in, returning what it changed; nothing for most entries.
+    pub fn apply(&mut self, entry: &LogEntry) -> Vec<GameChange> {
+        let before = self.clone();
+        self.fold(entry);
+        let mut changes = self.changes_since(&before);
+        changes.extend(happening(entry));
+        changes
+    }
+
+    /// Forget everything, for a game that restarted, returning what that
+    /// changed.
+    pub fn reset(&mut self) -> Vec<GameChange> {
+        let before = std::mem::take(self);
+        self.changes_since(&before)
+    }
+
+    fn changes_since(&self, before: &GameState) -> Vec<GameChange> {
+        let mut changes = Vec::new();
+        if self.account != before.account {
+            changes.push(GameChange::Account(self.account.clone()));
+        }
+        if self.mission != before.mission {
+            changes.push(GameChange::Mission(self.mission.clone()));
+        }
+        if self.squad_size != before.squad_size {
+            changes.push(GameChange::SquadSize(self.squad_size));
+        }
+        if self.role != before.role {
+            changes.push(GameChange::Role(self.role));
+        }
+        if self.phase != before.phase {
+            changes.push(GameChange::Phase(self.phase));
+        }
+        if self.host_migrations > before.host_migrations {
+            changes.push(GameChange::HostMigration);
+        }
+        changes.extend(
+            self.relics
+                .iter()
+                .filter(|x| !before.relics.contains(x))
+                .cloned()
+                .map(GameChange::Relic),
+        );
+        changes
+    }
+
+    fn fold(&mut self, entry: &LogEntry) {
+        match entry {
+            LogEntry::SysInfo(x) => {
+                if let Some(captures) = LoggedIn::new().captures(x) {
+                    let account = captures.name.content.to_string();
+                    // relics logged before it was known whose they are
+                    for relic in &mut self.relics {
+                        if relic.player.as_ref() == Some(&account) {
+                            relic.player = None;
+                        }
+                    }
+                    self.account = Some(account);
+                } else if let Some(captures) = LoadingLevel::new().captures(x) {
+                    self.phase = Some(Phase::Loading);
+                    self.level = Some(captures.level.content.to_string());
+                } else if x.starts_with(LEVEL_LOADED) {
+                    let orbiter = self
+                        .level
+                        .as_ref()
+                        .is_some_and(|level| ORBITER_LEVELS.iter().any(|x| level.contains(x)));
+                    match orbiter {
+                        true => {
+                            self.phase = Some(Phase::Orbiter);
+                            self.mission = None;
+                            self.role = None;
+                            self.host_migrations = 0;
+                            self.relics.clear();
+                        }
+                        false => self.phase = Some(Phase::InMission),
+                    }
+                }
+            }
+            LogEntry::NetInfo(x) => {
+                let count = SessionPlayers::new()
+                    .captures(x)
+                    .and_then(|x| x.count.content.parse().ok())
+                    .filter(|x| (1..=4).contains(x));
+                if let Some(count) = count {
+                    self.squad_size = count;
+                } else if x.trim() == HOST_MIGRATION_STARTED {
+                    self.host_migrations += 1;
+                }
+            }
+            LogEntry::ScriptInfo { script, content } if script == SQUAD_OVERLAY => {
+                if let Some(captures) = MissionName::new().captures(content) {
+                    let mission =
+                        Mission::from_log(captures.node.content, captures.kind.map(|x| x.content));
+                    if self.mission.as_ref() != Some(&mission) {
+                        self.host_migrations = 0;
+                    }
+                    self.mission = Some(mission);
+                } else if content.starts_with("Host loading") {
+                    self.role = Some(Role::Host);
+                } else if content.starts_with("Client loading") {
+                    self.role = Some(Role::Client);
+                }
+            }
+            LogEntry::ScriptInfo { script, content } if script == RELIC_SCRIPT => {
+                if let Some(relic) = self.relic_from_log(content) {
+                    // a player picking another relic replaces the one before
+                    self.relics.retain(|x| x.player != relic.player);
+                    self.relics.push(relic);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// the relic a script line says someone equipped; the local player's if
+    /// it doesn't say who, or names the logged in account
+    fn relic_from_log(&self, content: &str) -> Option<LoggedRelic> {
+        let captures = RelicEquipped::new().captures(content)?;
+        let player = captures
+            .player
+            .map(|x| x.content.to_string())
+            .filter(|x| Some(x) != self.account.as_ref());
+        Some(LoggedRelic {
+            player,
+            name: captures.relic.content.to_string(),
+            refinement: captures.refinement.content.parse().ok()?,
+        })
+    }
+}
+
+/// what an entry says happened without changing the state; the orbiter
+/// loading afterwards does that
+fn happening(entry: &LogEntry) -> Option<GameChange> {
+    let says = |message: &str, lines: &[&str]| lines.contains(&message.trim());
+    match entry {
+        LogEntry::ScriptInfo { content: x, .. } | LogEntry::SysInfo(x) if says(x, &ABORT_LINES) => {
+            Some(GameChange::MissionAborted)
+        }
+        LogEntry::NetInfo(x)
+        | LogEntry::NetError(x)
+        | LogEntry::Other {
+            system: System::Net,
+            message: x,
+            ..
+        } if says(x, &DISCONNECT_LINES) => Some(GameChange::Disconnected),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(state: &mut GameState, lines: &str) -> Vec<GameChange> {
+        lines
+            .lines()
+            .flat_map(|x| x.parse::<LogEntry>())
+            .flat_map(|x| state.apply(&x))
+            .collect()
+    }
+
+    #[test]
+    fn folds_a_fissure_run() {
+        let mut state = GameState::default();
+        let changes = apply(
+            &mut state,
+            "1.000 Sys [Info]: Logged in Tenno (5a0b1c2d3e4f)
+            2.000 Sys [Info]: Loading level /Lotus/Levels/Orbiter/Orbiter.level
+            3.000 Sys [Info]: Level loaded
+            3.500 Net [Info]: Num session players: 4",
+        );
+        assert_eq!(
+            changes,
+            [
+                GameChange::Account(Some("Tenno".to_string())),
+                GameChange::Phase(Some(Phase::Loading)),
+                GameChange::Phase(Some(Phase::Orbiter)),
+            ]
+        );
+
+        let changes = apply(
+            &mut state,
+            "10.000 Script [Info]: ThemedSquadOverlay.lua: Mission name: Hepit (Void) - Lith Fissure
+            10.100 Script [Info]: ThemedSquadOverlay.lua: Client loading
+            10.200 Sys [Info]: Loading level /Lotus/Levels/Proc/Orokin/OrokinCapture.level
+            11.000 Net [Info]: Num session players: 3
+            12.000 Sys [Info]: Level loaded
+            20.000 Net [Info]: Host migration started",
+        );
+        let hepit = Mission {
+            node: "Hepit (Void)".to_string(),
+            kind: Some("Lith Fissure".to_string()),
+            era: Some("lith".to_string()),
+        };
+        assert_eq!(
+            changes,
+            [
+                GameChange::Mission(Some(hepit.clone())),
+                GameChange::Role(Some(Role::Client)),
+                GameChange::Phase(Some(Phase::Loading)),
+                GameChange::SquadSize(3),
+                GameChange::Phase(Some(Phase::InMission)),
+                GameChange::HostMigration,
+            ]
+        );
+        assert_eq!(state.mission, Some(hepit));
+        assert_eq!(state.host_migrations, 1);
+
+        // back on the orbiter, the mission is over
+        let changes = apply(
+            &mut state,
+            "30.000 Sys [Info]: Loading level /Lotus/Levels/Orbiter/Orbiter.level
+            31.000 Sys [Info]: Level loaded",
+        );
+        assert_eq!(
+            changes,
+            [
+                GameChange::Phase(Some(Phase::Loading)),
+                GameChange::Mission(None),
+                GameChange::Role(None),
+                GameChange::Phase(Some(Phase::Orbiter)),
+            ]
+        );
+        assert_eq!(state.host_migrations, 0);
+        assert_eq!(state.account.as_deref(), Some("Tenno"));
+
+        // the game restarting forgets all of it
+        assert_eq!(
+            state.reset(),
+            [
+                GameChange::Account(None),
+                GameChange::SquadSize(4),
+                GameChange::Phase(None),
+            ]
+        );
+        assert_eq!(state, GameState::default());
+    }
+
+    #[test]
+    fn equipped_relics() {
+        let mut state = GameState::default();
+        let changes = apply(
+            &mut state,
+            "1.000 Sys [Info]: Logged in Tenno (5a0b1c2d3e4f)
+            2.000 Script [Info]: ProjectionManager.lua: Tenno equipped Lith B4 Relic [Intact]
+            3.000 Script [Info]: ProjectionManager.lua: Vaultrunner equipped Axi L4 Relic [Flawless]
+            4.000 Script [Info]: ProjectionManager.lua: equipped Meso O4 Relic [Radiant]
+            4.500 Script [Info]: ProjectionManager.lua: Ordis equipped Neo N16 Relic [Intact]
+            4.600 Script [Info]: ChatRedux.lua: Ordis equipped Axi A1 Relic [Radiant]",
+        );
+        let relic = |player: Option<&str>, name: &str, refinement| LoggedRelic {
+            player: player.map(str::to_string),
+            name: name.to_string(),
+            refinement,
+        };
+        assert_eq!(
+            changes[1..],
+            [
+                GameChange::Relic(relic(None, "Lith B4", Refinement::Intact)),
+                GameChange::Relic(relic(Some("Vaultrunner"), "Axi L4", Refinement::Flawless)),
+                GameChange::Relic(relic(None, "Meso O4", Refinement::Radiant)),
+                GameChange::Relic(relic(Some("Ordis"), "Neo N16", Refinement::Intact)),
+            ]
+        );
+        // the local player switched relics
+        assert_eq!(
+            state.relics,
+            [
+                relic(Some("Vaultrunner"), "Axi L4", Refinement::Flawless),
+                relic(None, "Meso O4", Refinement::Radiant),
+                relic(Some("Ordis"), "Neo N16", Refinement::Intact),
+            ]
+        );
+
+        apply(
+            &mut state,
+            "5.000 Sys [Info]: Loading level /Lotus/Levels/Orbiter/Orbiter.level
+            6.000 Sys [Info]: Level loaded",
+        );
+        assert!(state.relics.is_empty());
+    }
+
+    #[test]
+    fn relics_logged_before_the_account() {
+        let mut state = GameState::default();
+        apply(
+            &mut state,
+            "1.000 Script [Info]: ProjectionManager.lua: Tenno equipped Lith B4 Relic [Intact]
+            2.000 Sys [Info]: Logged in Tenno (5a0b1c2d3e4f)",
+        );
+        assert_eq!(state.relics[0].player, None);
+    }
+
+    #[test]
+    fn aborts_and_disconnects() {
+        let mut state = GameState::default();
+        let changes = apply(
+            &mut state,
+            "1.000 Script [Info]: ThemedSquadOverlay.lua: Mission name: Hepit (Void) - Lith Fissure
+            1.500 Script [Info]: Menu.lua: Abort mission?
+            2.000 Script [Info]: EndOfMatch.lua: Mission aborted
+            3.000 Net [Warning]: Lost connection to host
+            4.000 Net [Info]: Client disconnected: Vaultrunner",
+        );
+        assert_eq!(
+            changes[1..],
+            [GameChange::MissionAborted, GameChange::Disconnected]
+        );
+        // they're just news, the mission is over once the orbiter loads
+        assert!(state.mission.is_some());
+    }
+
+    #[test]
+    fn a_host_migration_counts_once() {
+        let mut state = GameState::default();
+        let changes = apply(
+            &mut state,
+            "1.000 Net [Info]: Host migration started
+            1.100 Net [Warning]: Host migration: waiting for clients
+            1.200 Net [Error]: Host migration timed out, retrying
+            1.300 Net [Info]: Host migration complete",
+        );
+        assert_eq!(changes, [GameChange::HostMigration]);
+        assert_eq!(state.host_migrations, 1);
+    }
+
+    #[test]
+    fn missions_without_a_fissure() {
+        assert_eq!(Mission::from_log("Olympus (Mars)", None).era, None);
+        assert_eq!(
+            Mission::from_log("Ukko (Void)", Some("Axi Capture")).era,
+            Some("axi".to_string())
+        );
+        // not an era, just a word that starts like one
+        assert_eq!(Mission::from_log("Lithium (Void)", None).era, None);
+    }
+}
@@ -15,6 +15,8 @@ pub struct CardRegion {
     pub x: u32,
     /// card width
     pub width: u32,
+    /// top edge of the card frame
+    pub top: u32,
     /// bottom edge of the item-name text block
     pub text_bottom: u32,
     /// height of one line of item-name text
@@ -27,6 +29,7 @@ pub struct CardRegion {
 pub fn reward_card_regions(screen_width: u32, screen_height: u32, count: usize) -> Vec<CardRegion> {
     let middle = screen_width / 2;
     let frame_width = (screen_width * 243) / 1920;
+    let frame_top = (screen_height * 224) / 1080;
     let frame_bottom = (screen_height * 460) / 1080;
     let text_height = (screen_height * 24) / 1080;
     let start_points = match count {
@@ -50,6 +53,7 @@ pub fn reward_card_regions(screen_width: u32, screen_height: u32, count: usize)
         .map(|x| CardRegion {
             x,
             width: frame_width,
+            top: frame_top,
             text_bottom: frame_bottom,
             line_height: text_height,
         })
@@ -65,6 +69,7 @@ mod tests {
         let r = reward_card_regions(1920, 1080, 4);
         assert_eq!(r.iter().map(|c| c.x).collect::<Vec<_>>(), vec![474, 717, 960, 1203]);
         assert!(r.iter().all(|c| c.width == 243));
+        assert!(r.iter().all(|c| c.top == 224));
         assert!(r.iter().all(|c| c.text_bottom == 460));
         assert!(r.iter().all(|c| c.line_height == 24));
     }
@@ -74,6 +79,7 @@ mod tests {
         let r = reward_card_regions(2560, 1440, 4);
         assert_eq!(r.iter().map(|c| c.x).collect::<Vec<_>>(), vec![632, 956, 1280, 1604]);
         assert!(r.iter().all(|c| c.width == 324));
+        assert!(r.iter().all(|c| c.top == 298));
         assert!(r.iter().all(|c| c.text_bottom == 613));
         assert!(r.iter().all(|c| c.line_height == 32));
     }
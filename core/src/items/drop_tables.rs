@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use super::{
+    items::{Item, ItemDatabase},
+    relics::Relic,
+};
+
+/// slug the drop-data file uses for a forma blueprint, which isn't an [`Item`]
+pub const FORMA_SLUG: &str = "forma_blueprint";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Rarity {
+    Common,
+    Uncommon,
+    Rare,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DropEntry {
+    Forma,
+    /// id of an [`Item`]
+    Item(String),
+}
+
+/// What one relic can drop. Intact relics have three common, two uncommon
+/// and one rare entry, but nothing here assumes that.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RelicDropTable {
+    /// id of the [`Relic`]
+    pub relic_id: String,
+    pub common: Vec<DropEntry>,
+    pub uncommon: Vec<DropEntry>,
+    pub rare: Vec<DropEntry>,
+}
+
+impl RelicDropTable {
+    pub fn entries(&self) -> impl Iterator<Item = (Rarity, &DropEntry)> {
+        fn tier(
+            rarity: Rarity,
+            entries: &[DropEntry],
+        ) -> impl Iterator<Item = (Rarity, &DropEntry)> {
+            entries.iter().map(move |x| (rarity, x))
+        }
+        tier(Rarity::Common, &self.common)
+            .chain(tier(Rarity::Uncommon, &self.uncommon))
+            .chain(tier(Rarity::Rare, &self.rare))
+    }
+
+    /// the prime parts this relic drops, forma left out
+    pub fn items<'a>(&'a self, db: &'a ItemDatabase) -> impl Iterator<Item = (Rarity, &'a Item)> {
+        self.entries().flat_map(|(rarity, entry)| match entry {
+            DropEntry::Forma => None,
+            DropEntry::Item(id) => db.items.get(id).map(|x| (rarity, x)),
+        })
+    }
+}
+
+/// Drop tables of every relic, keyed by relic id.
+///
+/// warframe.market doesn't know what relics drop, so they come from a local
+/// JSON file mapping relic slugs to the slugs they drop, by rarity:
+///
+/// ```json
+/// {
+///   "lith_b4_relic": {
+///     "common": ["okina_prime_blade", "forma_blueprint", "shade_prime_systems"],
+///     "uncommon": ["baruuk_prime_chassis_blueprint", "sybaris_prime_stock"],
+///     "rare": ["okina_prime_handle"]
+///   }
+/// }
+/// ```
+///
+/// Slugs are warframe.market's. [`FORMA_SLUG`] stands for forma; any other
+/// entry has to be a prime part in the item database. Unknown relics and
+/// parts are skipped with a warning, so a file that's a little ahead of or
+/// behind the market still loads.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DropTables {
+    tables: HashMap<String, RelicDropTable>,
+}
+
+impl DropTables {
+    pub fn from_json(
+        bytes: &[u8],
+        items: &HashMap<String, Item>,
+        relics: &HashMap<String, Relic>,
+    ) -> Result<Self, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Tiers {
+            #[serde(default)]
+            common: Vec<String>,
+            #[serde(default)]
+            uncommon: Vec<String>,
+            #[serde(default)]
+            rare: Vec<String>,
+        }
+        let file = serde_json::from_slice::<HashMap<String, Tiers>>(bytes)?;
+        let item_ids = items
+            .values()
+            .map(|x| (x.id_name.as_str(), x.id.as_str()))
+            .collect::<HashMap<_, _>>();
+        let relic_ids = relics
+            .values()
+            .map(|x| (x.id_name.as_str(), x.id.as_str()))
+            .collect::<HashMap<_, _>>();
+        let resolve = |relic: &str, slugs: Vec<String>| {
+            slugs
+                .into_iter()
+                .flat_map(|slug| match slug.as_str() {
+                    FORMA_SLUG => Some(DropEntry::Forma),
+                    _ => match item_ids.get(slug.as_str()) {
+                        Some(id) => Some(DropEntry::Item(id.to_string())),
+                        None => {
+                            event!(Level::WARN, "{relic} drops unknown item {slug}, skipping");
+                            None
+                        }
+                    },
+                })
+                .collect()
+        };
+        let tables = file
+            .into_iter()
+            .flat_map(|(slug, tiers)| {
+                let Some(relic_id) = relic_ids.get(slug.as_str()) else {
+                    event!(Level::WARN, "drop table for unknown relic {slug}, skipping");
+                    return None;
+                };
+                let table = RelicDropTable {
+                    relic_id: relic_id.to_string(),
+                    common: resolve(&slug, tiers.common),
+                    uncommon: resolve(&slug, tiers.uncommon),
+                    rare: resolve(&slug, tiers.rare),
+                };
+                Some((table.relic_id.clone(), table))
+            })
+            .collect();
+        Ok(Self { tables })
+    }
+
+    pub fn for_relic(&self, relic_id: &str) -> Option<&RelicDropTable> {
+        self.tables.get(relic_id)
+    }
+
+    /// every relic that drops `item_id`, with the rarity it drops at
+    pub fn relics_dropping<'a>(
+        &'a self,
+        item_id: &'a str,
+    ) -> impl Iterator<Item = (&'a RelicDropTable, Rarity)> {
+        self.tables.values().flat_map(move |table| {
+            table
+                .entries()
+                .find(|(_, x)| matches!(x, DropEntry::Item(id) if id == item_id))
+                .map(|(rarity, _)| (table, rarity))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        items::relics::fetch_relics,
+        test_support::{mock_database, MockDatabase},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn resolves_both_ways() {
+        let MockDatabase {
+            market,
+            identifiers,
+            items,
+            sets,
+        } = mock_database().await;
+        let relics = fetch_relics(&market, &identifiers, &|_| {})
+            .await
+            .unwrap()
+            .data
+            .into_iter()
+            .map(|x| (x.id.clone(), x))
+            .collect::<HashMap<_, _>>();
+        let json = br#"{
+            "lith_b4_relic": {
+                "common": ["okina_prime_blade", "forma_blueprint", "not_a_prime_part"],
+                "uncommon": ["shade_prime_systems"],
+                "rare": ["okina_prime_handle"]
+            },
+            "meso_o4_relic": {
+                "common": ["okina_prime_handle"]
+            },
+            "not_a_relic": {
+                "rare": ["okina_prime_handle"]
+            }
+        }"#;
+        let tables = DropTables::from_json(json, &items, &relics).unwrap();
+        assert_eq!(tables.len(), 2);
+
+        let db = ItemDatabase {
+            items,
+            sets,
+            ..Default::default()
+        };
+        let id_of = |slug: &str| {
+            db.items
+                .values()
+                .find(|x| x.id_name == slug)
+                .unwrap()
+                .id
+                .clone()
+        };
+        let lith = relics
+            .values()
+            .find(|x| x.id_name == "lith_b4_relic")
+            .unwrap();
+        let table = tables.for_relic(&lith.id).unwrap();
+        assert_eq!(
+            table.common,
+            vec![
+                DropEntry::Item(id_of("okina_prime_blade")),
+                DropEntry::Forma
+            ]
+        );
+        assert_eq!(
+            table
+                .items(&db)
+                .map(|(rarity, x)| (rarity, x.id_name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Rarity::Common, "okina_prime_blade"),
+                (Rarity::Uncommon, "shade_prime_systems"),
+                (Rarity::Rare, "okina_prime_handle"),
+            ]
+        );
+
+        let handle = id_of("okina_prime_handle");
+        let mut dropping = tables
+            .relics_dropping(&handle)
+            .map(|(table, rarity)| (relics[&table.relic_id].id_name.as_str(), rarity))
+            .collect::<Vec<_>>();
+        dropping.sort();
+        assert_eq!(
+            dropping,
+            vec![
+                ("lith_b4_relic", Rarity::Rare),
+                ("meso_o4_relic", Rarity::Common)
+            ]
+        );
+    }
+}
@@ -1,18 +1,16 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-use crate::items::orders::{Order, OrderType, Platform};
 use crate::items::ItemIdentifier;
-use crate::{
-    config::client,
-    items::{Data, I18n},
-};
-use futures::stream::{FuturesUnordered, StreamExt};
+use crate::{config::MarketClient, items::I18n};
 use serde::{Deserialize, Serialize};
 
-use super::orders::{fetch_orders, UserStatus};
-use super::ReqwestSerdeError;
+use super::drop_tables::DropTables;
+use super::orders::fetch_orders;
+use super::price::PriceStrategy;
+use super::relics::Relic;
+use super::{fetch_all, Data, OnProgress, ReqwestSerdeError};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ItemSet {
     pub id: String,
     pub id_name: String,
@@ -31,44 +29,72 @@ pub struct Item {
     pub quantity_for_set: u32,
 }
 
-impl Item {
-    pub async fn price(&self) -> Result<u32, ReqwestSerdeError> {
-        fn not_offlines(order: &&Order) -> bool {
-            order.user.status != UserStatus::Offline
-        }
-        fn any(_: &&Order) -> bool {
-            true
-        }
-        let orders = fetch_orders(&self.id_name)
-            .await?
-            .into_iter()
-            .filter(|x| {
-                x.user.platform == Platform::Pc
-                    && x.user.locale == "en"
-                    && x.order_type == OrderType::Buy
-            })
-            .collect::<Vec<_>>();
-        let filter = if orders.iter().filter(not_offlines).count() > 3 {
-            not_offlines
-        } else {
-            any
-        };
-        let mut orders = orders
-            .iter()
-            .filter(filter)
-            .map(|x| x.platinum)
-            .collect::<Vec<_>>();
-        orders.sort();
-        if orders.is_empty() {
-            return Ok(0);
+/// Every prime part and set, as built by [`fetch_items_and_sets`], plus
+/// relics and what they drop. All maps are keyed by id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemDatabase {
+    pub items: HashMap<String, Item>,
+    pub sets: HashMap<String, ItemSet>,
+    pub relics: HashMap<String, Relic>,
+    /// empty unless a drop-data file was configured
+    pub drop_tables: DropTables,
+}
+
+impl ItemDatabase {
+    pub fn set_of(&self, item: &Item) -> Option<&ItemSet> {
+        self.sets.get(&item.set_id)
+    }
+
+    pub fn parts<'a>(&'a self, set: &'a ItemSet) -> impl Iterator<Item = &'a Item> {
+        set.part_ids.iter().flat_map(|id| self.items.get(id))
+    }
+
+    /// `Lith B4`, `lith b4 relic` and `lith_b4_relic` all find the same relic
+    pub fn relic_by_name(&self, name: &str) -> Option<&Relic> {
+        let mut slug = name.trim().to_lowercase().replace(' ', "_");
+        if !slug.ends_with("_relic") {
+            slug += "_relic";
         }
-        Ok(orders[orders.len() / 2])
+        self.relics.values().find(|x| x.id_name == slug)
     }
 }
 
-pub async fn fetch_items_and_sets(
-    identifiers: &[ItemIdentifier],
-) -> Result<(HashMap<String, Item>, HashMap<String, ItemSet>), ReqwestSerdeError> {
+impl Item {
+    pub async fn price(
+        &self,
+        market: &MarketClient,
+        strategy: &dyn PriceStrategy,
+    ) -> Result<u32, ReqwestSerdeError> {
+        let orders = fetch_orders(market, &self.id_name).await?;
+        Ok(strategy.price(&orders))
+    }
+}
+
+/// prime slugs whose `/v2/item/{slug}/set` makes up the item database, set
+/// roots included
+fn set_slugs(identifiers: &[ItemIdentifier]) -> impl Iterator<Item = &String> {
+    identifiers
+        .iter()
+        .flat_map(|x| match x {
+            ItemIdentifier::Relic { id_name: _ } => None,
+            ItemIdentifier::Item { id_name } => Some(id_name),
+        })
+        .filter(|x| x.contains("prime"))
+        .filter(|x| !x.contains("primed"))
+        .filter(|x| "gotva_prime" != x.as_str())
+}
+
+/// the slug of the set `slug` is in, going by warframe.market naming parts
+/// `<name>_prime_<part>` and their set `<name>_prime_set`
+fn set_slug_of(slug: &str) -> Option<String> {
+    let (name, _) = slug.split_once("_prime_")?;
+    Some(format!("{name}_prime_set"))
+}
+
+async fn fetch_set(
+    market: &MarketClient,
+    name: &str,
+) -> Result<Data<(ItemSet, Vec<Item>)>, ReqwestSerdeError> {
     #[derive(Debug, Serialize, Deserialize)]
     pub struct SetItem {
         id: String,
@@ -87,63 +113,241 @@ pub async fn fetch_items_and_sets(
     pub struct SetResponse {
         items: Vec<SetItem>,
     }
-    let items = identifiers
-        .iter()
-        .flat_map(|x| match x {
-            ItemIdentifier::Relic { id_name: _ } => None,
-            ItemIdentifier::Item { id_name } => Some(id_name),
+    let Data { api_version, data } = market
+        .get_data::<SetResponse>(&format!("/v2/item/{name}/set"))
+        .await?;
+    let (roots, parts): (Vec<_>, Vec<_>) = data.items.into_iter().partition(|n| n.set_root);
+    let root = roots.into_iter().next().unwrap();
+    let set = ItemSet {
+        id: root.id,
+        id_name: root.slug,
+        name: root.i18n.en.name,
+        part_ids: parts.iter().map(|x| x.id.clone()).collect(),
+    };
+    let parts = parts
+        .into_iter()
+        .map(|x| Item {
+            id: x.id,
+            id_name: x.slug,
+            name: x.i18n.en.name,
+            trading_tax: x.trading_tax,
+            set_id: set.id.clone(),
+            ducats: x.ducats,
+            quantity_for_set: x.quantity_for_set,
         })
-        .filter(|x| x.contains("prime"))
-        .filter(|x| !x.contains("primed"))
-        .filter(|x| "gotva_prime" != x.as_str())
-        .map(|name| async move {
-            let set = client()
-                .await
-                .get(format!(
-                    "https://api.warframe.market/v2/item/{name}/set"
-                ))
-                .send()
-                .await?
-                .json::<Data<SetResponse>>()
-                .await?
-                .data;
-            let (roots, parts): (Vec<_>, Vec<_>) =
-                set.items.into_iter().partition(|n| n.set_root);
-            let root = roots.into_iter().next().unwrap();
-            let set = ItemSet {
-                id: root.id,
-                id_name: root.slug,
-                name: root.i18n.en.name,
-                part_ids: parts.iter().map(|x| x.id.clone()).collect(),
-            };
-            let parts = parts
-                .into_iter()
-                .map(|x| Item {
-                    id: x.id,
-                    id_name: x.slug,
-                    name: x.i18n.en.name,
-                    trading_tax: x.trading_tax,
-                    set_id: set.id.clone(),
-                    ducats: x.ducats,
-                    quantity_for_set: x.quantity_for_set,
+        .collect::<Vec<_>>();
+    Ok(Data {
+        api_version,
+        data: (set, parts),
+    })
+}
+
+/// put a freshly fetched set in place of whatever version of it is there,
+/// parts that left the set included
+fn insert_set(
+    items: &mut HashMap<String, Item>,
+    sets: &mut HashMap<String, ItemSet>,
+    set: ItemSet,
+    parts: Vec<Item>,
+) {
+    if let Some(old) = sets.remove(&set.id) {
+        for id in old.part_ids {
+            items.remove(&id);
+        }
+    }
+    sets.insert(set.id.clone(), set);
+    for p in parts {
+        items.insert(p.id.clone(), p);
+    }
+}
+
+pub async fn fetch_items_and_sets(
+    market: &MarketClient,
+    identifiers: &[ItemIdentifier],
+    on_progress: OnProgress<'_>,
+) -> Result<Data<(HashMap<String, Item>, HashMap<String, ItemSet>)>, ReqwestSerdeError> {
+    let requests = set_slugs(identifiers)
+        .map(|name| fetch_set(market, name))
+        .collect::<Vec<_>>();
+    let fetched = fetch_all(requests, on_progress).await?;
+
+    Ok(fetched.into_iter().collect::<Data<Vec<_>>>().map(|items| {
+        let (mut parts, mut sets) = (HashMap::new(), HashMap::new());
+        for (set, part) in items {
+            insert_set(&mut parts, &mut sets, set, part);
+        }
+        (parts, sets)
+    }))
+}
+
+/// Bring `items` and `sets`, built from `previous`, up to date with `current`
+/// without refetching everything: sets are fetched, once each, only for slugs
+/// that are new and for sets that lost a slug. Sets with no slug left are
+/// dropped.
+pub async fn update_items_and_sets(
+    market: &MarketClient,
+    previous: &[ItemIdentifier],
+    current: &[ItemIdentifier],
+    mut items: HashMap<String, Item>,
+    mut sets: HashMap<String, ItemSet>,
+    on_progress: OnProgress<'_>,
+) -> Result<Data<(HashMap<String, Item>, HashMap<String, ItemSet>)>, ReqwestSerdeError> {
+    let previous = set_slugs(previous).collect::<HashSet<_>>();
+    let current = set_slugs(current).collect::<HashSet<_>>();
+
+    // a new set's slugs all fetch the same set, through its root if it's listed
+    let mut fetch = current
+        .difference(&previous)
+        .map(|slug| {
+            set_slug_of(slug)
+                .and_then(|x| current.get(&x))
+                .unwrap_or(slug)
+                .as_str()
+        })
+        .collect::<BTreeSet<_>>();
+    let changed_sets = previous
+        .difference(&current)
+        .flat_map(|slug| {
+            sets.values()
+                .find(|x| &&x.id_name == slug)
+                .map(|x| x.id.clone())
+                .or_else(|| {
+                    items
+                        .values()
+                        .find(|x| &&x.id_name == slug)
+                        .map(|x| x.set_id.clone())
                 })
-                .collect::<Vec<_>>();
-            Ok((set, parts))
         })
-        .collect::<FuturesUnordered<_>>()
-        .collect::<Vec<_>>()
-        .await
+        .collect::<HashSet<_>>();
+    for set_id in changed_sets {
+        let Some(set) = sets.remove(&set_id) else {
+            continue;
+        };
+        let parts = set
+            .part_ids
+            .iter()
+            .flat_map(|id| items.remove(id))
+            .collect::<Vec<_>>();
+        // any slug still listed fetches the whole set
+        if let Some(slug) = std::iter::once(&set.id_name)
+            .chain(parts.iter().map(|x| &x.id_name))
+            .find(|x| current.contains(x))
+        {
+            fetch.insert(current.get(slug).unwrap().as_str());
+        }
+    }
+
+    let requests = fetch
         .into_iter()
-        .collect::<Result<Vec<_>, ReqwestSerdeError>>()?;
-
-    Ok(items.into_iter().fold(
-        (HashMap::new(), HashMap::new()),
-        |(mut parts, mut sets), (set, part)| {
-            sets.insert(set.id.clone(), set);
-            for p in part {
-                parts.insert(p.id.clone(), p);
+        .map(|name| fetch_set(market, name))
+        .collect::<Vec<_>>();
+    let fetched = fetch_all(requests, on_progress).await?;
+    Ok(fetched
+        .into_iter()
+        .collect::<Data<Vec<_>>>()
+        .map(|fetched| {
+            for (set, parts) in fetched {
+                insert_set(&mut items, &mut sets, set, parts);
             }
-            (parts, sets)
-        },
-    ))
+            (items, sets)
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{
+        items::{item_identifiers::get_item_identifiers, FetchProgress},
+        test_support::{mock_database, MockDatabase},
+    };
+
+    use super::*;
+
+    fn is_okina(x: &ItemIdentifier) -> bool {
+        match x {
+            ItemIdentifier::Item { id_name } => id_name.starts_with("okina_prime"),
+            ItemIdentifier::Relic { id_name: _ } => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn update_fetches_only_new_sets() {
+        let MockDatabase {
+            market,
+            identifiers: current,
+            items,
+            sets,
+        } = mock_database().await;
+        let (mut old_items, mut old_sets) = (items.clone(), sets.clone());
+        old_items.retain(|_, x| !x.id_name.starts_with("okina_prime"));
+        old_sets.retain(|_, x| x.id_name != "okina_prime_set");
+        let previous = get_item_identifiers(&market)
+            .await
+            .unwrap()
+            .data
+            .into_iter()
+            .filter(|x| !is_okina(x))
+            .collect::<Vec<_>>();
+
+        let reports = Mutex::new(Vec::new());
+        let (new_items, new_sets) =
+            update_items_and_sets(&market, &previous, &current, old_items, old_sets, &|x| {
+                reports.lock().unwrap().push(x)
+            })
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(new_items, items);
+        assert_eq!(
+            new_sets.keys().collect::<HashSet<_>>(),
+            sets.keys().collect::<HashSet<_>>()
+        );
+        // okina's set once, not once per part
+        assert_eq!(
+            reports.into_inner().unwrap().last(),
+            Some(&FetchProgress {
+                done: 1,
+                failed: 0,
+                total: 1
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn update_drops_removed_sets_without_fetching() {
+        let MockDatabase {
+            market,
+            identifiers: previous,
+            items,
+            sets,
+        } = mock_database().await;
+        let current = get_item_identifiers(&market)
+            .await
+            .unwrap()
+            .data
+            .into_iter()
+            .filter(|x| !is_okina(x))
+            .collect::<Vec<_>>();
+
+        let reports = Mutex::new(Vec::new());
+        let (new_items, new_sets) = update_items_and_sets(
+            &market,
+            &previous,
+            &current,
+            items.clone(),
+            sets.clone(),
+            &|x| reports.lock().unwrap().push(x),
+        )
+        .await
+        .unwrap()
+        .data;
+        assert!(new_items.values().all(|x| !x.id_name.starts_with("okina")));
+        assert_eq!(new_items.len(), items.len() - 3);
+        assert_eq!(new_sets.len(), sets.len() - 1);
+        assert_eq!(
+            reports.into_inner().unwrap(),
+            vec![FetchProgress::default()]
+        );
+    }
 }
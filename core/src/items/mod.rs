@@ -1,22 +1,35 @@
+pub mod drop_tables;
 pub mod item_identifiers;
+#[allow(clippy::module_inception)]
 pub mod items;
 pub mod orders;
+pub mod price;
+pub mod price_cache;
+pub mod relic_value;
 pub mod relics;
+pub mod snapshot;
 
 use std::{
     collections::HashMap,
     error::Error,
     fmt::{Debug, Display},
-    fs::{File, OpenOptions},
     future::Future,
-    io::{BufReader, BufWriter},
-    path::Path,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
+use crate::config::MarketClient;
+use chrono::{DateTime, Utc};
+use drop_tables::DropTables;
+use futures::stream::{FuturesUnordered, StreamExt};
 use item_identifiers::{get_item_identifiers, ItemIdentifier};
-use items::{fetch_items_and_sets, Item, ItemSet};
+use items::{fetch_items_and_sets, update_items_and_sets, Item, ItemSet};
 use relics::{fetch_relics, Relic};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::*;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ReqwestSerdeError {
@@ -29,12 +42,39 @@ pub enum ReqwestSerdeError {
 }
 
 /// v2 responses are wrapped in `{"apiVersion": ..., "data": ..., "error": ...}`.
-/// We only care about `data`; the rest is ignored.
+/// `apiVersion` is kept to stamp cache files with; `error` is ignored.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Data<T> {
+    #[serde(rename = "apiVersion", default)]
+    pub api_version: Option<String>,
     pub data: T,
 }
 
+impl<T> Data<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Data<U> {
+        Data {
+            api_version: self.api_version,
+            data: f(self.data),
+        }
+    }
+}
+
+/// the data of several responses, with the `apiVersion` the last of them
+/// reported
+impl<T> FromIterator<Data<T>> for Data<Vec<T>> {
+    fn from_iter<I: IntoIterator<Item = Data<T>>>(iter: I) -> Self {
+        let mut api_version = None;
+        let data = iter
+            .into_iter()
+            .map(|x| {
+                api_version = x.api_version.or(api_version.take());
+                x.data
+            })
+            .collect();
+        Data { api_version, data }
+    }
+}
+
 /// Shared v2 i18n shape. Every item carries localized strings under `i18n.<lang>`;
 /// we only ever read English.
 #[derive(Debug, Serialize, Deserialize)]
@@ -47,6 +87,43 @@ pub struct I18nEn {
     pub name: String,
 }
 
+/// How far a fetch that fans out into one request per item has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FetchProgress {
+    /// finished requests, failed ones included
+    pub done: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+/// called with the running tally once up front and after every request
+pub type OnProgress<'a> = &'a (dyn Fn(FetchProgress) + Sync);
+
+/// Run `requests` concurrently, reporting each completion to `on_progress`.
+/// All of them run to completion even if some fail; the first error is
+/// returned afterwards.
+pub(crate) async fn fetch_all<T, Fut: Future<Output = Result<T, ReqwestSerdeError>>>(
+    requests: Vec<Fut>,
+    on_progress: OnProgress<'_>,
+) -> Result<Vec<T>, ReqwestSerdeError> {
+    let mut progress = FetchProgress {
+        total: requests.len(),
+        ..Default::default()
+    };
+    on_progress(progress);
+    let mut pending = requests.into_iter().collect::<FuturesUnordered<_>>();
+    let mut results = Vec::with_capacity(progress.total);
+    while let Some(result) = pending.next().await {
+        progress.done += 1;
+        if result.is_err() {
+            progress.failed += 1;
+        }
+        on_progress(progress);
+        results.push(result);
+    }
+    results.into_iter().collect()
+}
+
 #[derive(Debug)]
 pub enum CacheError<T> {
     CreateFileError(std::io::Error),
@@ -66,68 +143,677 @@ impl<T: Display> Display for CacheError<T> {
 
 impl<T: Display + Error> Error for CacheError<T> {}
 
+/// Bump whenever the shape of anything written by [`cache_in_file`] changes;
+/// files with another version are refetched instead of failing to parse.
+pub const CACHE_SCHEMA_VERSION: u32 = 1;
+
+/// Item data older than this is still served, but refreshed in the background.
+pub const ITEM_CACHE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What actually lands in a cache file: the data plus enough metadata to tell
+/// whether it can still be trusted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEnvelope<T> {
+    pub schema_version: u32,
+    pub fetched_at: DateTime<Utc>,
+    /// `apiVersion` warframe.market reported while the data was fetched
+    pub api_version: Option<String>,
+    pub data: T,
+}
+
+/// A cache hit (or a fresh fetch after a miss).
+#[derive(Debug)]
+pub struct Cached<T> {
+    pub data: T,
+    pub fetched_at: DateTime<Utc>,
+    /// older than the max age the caller asked for; worth refreshing
+    pub stale: bool,
+}
+
+/// Advisory lock on `<path>.lock`, released on drop. The cli and the overlay
+/// may share one `CACHE_PATH`, so every cache file access goes through one.
+/// It's a separate file because the cache file itself gets replaced by rename.
+struct CacheLock {
+    _file: std::fs::File,
+}
+
+impl CacheLock {
+    async fn acquire(path: &Path, exclusive: bool) -> std::io::Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        // flock blocks, keep it off the runtime threads
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(lock_path)?;
+            if exclusive {
+                file.lock()?;
+            } else {
+                file.lock_shared()?;
+            }
+            Ok(Self { _file: file })
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+}
+
+async fn read_envelope<T: DeserializeOwned>(path: &Path) -> Option<CacheEnvelope<T>> {
+    #[derive(Deserialize)]
+    struct Header {
+        schema_version: Option<u32>,
+    }
+    let bytes = match fs::read(path).await {
+        Ok(x) => x,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            event!(Level::WARN, "cache {path:?} unreadable, refetching: {e}");
+            return None;
+        }
+    };
+    match serde_json::from_slice::<Header>(&bytes) {
+        Ok(Header {
+            schema_version: Some(CACHE_SCHEMA_VERSION),
+        }) => {}
+        Ok(Header { schema_version }) => {
+            event!(
+                Level::INFO,
+                "cache {path:?} has schema {schema_version:?}, want {CACHE_SCHEMA_VERSION}, refetching"
+            );
+            return None;
+        }
+        Err(e) => {
+            event!(Level::WARN, "cache {path:?} is corrupt, refetching: {e}");
+            return None;
+        }
+    }
+    match serde_json::from_slice(&bytes) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            event!(
+                Level::WARN,
+                "cache {path:?} doesn't match its schema, refetching: {e}"
+            );
+            None
+        }
+    }
+}
+
+/// Replace `path` with `envelope` atomically, see [`write_atomic`]. Callers
+/// hold the exclusive [`CacheLock`].
+async fn write_envelope<T: Serialize, E>(
+    path: &Path,
+    envelope: &CacheEnvelope<T>,
+) -> Result<(), CacheError<E>> {
+    let bytes = serde_json::to_vec(envelope).map_err(CacheError::SerdeError)?;
+    write_atomic(path, &bytes)
+        .await
+        .map_err(CacheError::CreateFileError)
+}
+
+/// Replace `path` atomically: write a temp file next to it, fsync, rename
+/// over. A crash at any point leaves either the old file or the new one.
+async fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+    let write = async {
+        let mut file = fs::File::create(&tmp_path).await?;
+        file.write_all(bytes).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp_path, path).await?;
+        // persist the rename itself; not every platform can open a directory
+        if let Some(parent) = path.parent() {
+            if let Ok(dir) = fs::File::open(parent).await {
+                let _ = dir.sync_all().await;
+            }
+        }
+        Ok(())
+    };
+    let result: std::io::Result<()> = write.await;
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path).await;
+    }
+    result
+}
+
+fn envelope<T>(fetched: Data<T>) -> CacheEnvelope<T> {
+    CacheEnvelope {
+        schema_version: CACHE_SCHEMA_VERSION,
+        fetched_at: Utc::now(),
+        api_version: fetched.api_version,
+        data: fetched.data,
+    }
+}
+
+fn cached<T>(envelope: CacheEnvelope<T>, max_age: Duration) -> Cached<T> {
+    let age = (Utc::now() - envelope.fetched_at)
+        .to_std()
+        .unwrap_or_default();
+    Cached {
+        data: envelope.data,
+        fetched_at: envelope.fetched_at,
+        stale: age > max_age,
+    }
+}
+
+/// read `path` under a shared lock; `None` on anything but a current-schema
+/// envelope
+async fn cache_read<T: DeserializeOwned>(path: &Path) -> Option<CacheEnvelope<T>> {
+    let _lock = match CacheLock::acquire(path, false).await {
+        Ok(x) => x,
+        Err(e) => {
+            event!(Level::WARN, "couldn't lock cache {path:?}: {e}");
+            return None;
+        }
+    };
+    read_envelope(path).await
+}
+
+/// overwrite `path` with `envelope` under the exclusive lock
+async fn cache_write<T: Serialize, E>(
+    path: &Path,
+    envelope: &CacheEnvelope<T>,
+) -> Result<(), CacheError<E>> {
+    let _lock = CacheLock::acquire(path, true)
+        .await
+        .map_err(CacheError::CreateFileError)?;
+    write_envelope(path, envelope).await
+}
+
+/// fetch with `create` and overwrite the cache file, whatever it holds. the
+/// lock is only taken for the write, so readers never wait on the network
+async fn cache_refresh<
+    E: Error + Display,
+    T: DeserializeOwned + Serialize,
+    Fut: Future<Output = Result<Data<T>, E>>,
+    F: FnOnce() -> Fut,
+>(
+    path: &Path,
+    create: F,
+) -> Result<Cached<T>, CacheError<E>> {
+    let data = create().await.map_err(CacheError::InnerError)?;
+    let envelope = envelope(data);
+    cache_write(path, &envelope).await?;
+    Ok(cached(envelope, Duration::MAX))
+}
+
+/// Read `path` if it holds a current-schema envelope, otherwise fetch with
+/// `create` and write it. Data older than `max_age` is returned anyway,
+/// flagged `stale`, so callers can serve it while refreshing. On a miss the
+/// exclusive lock is held through the fetch, so a second process starting at
+/// the same time waits and reads the result instead of fetching too.
 async fn cache_in_file<
     E: Error + Display,
-    P: AsRef<Path>,
     T: DeserializeOwned + Serialize,
-    Fut: Future<Output = Result<T, E>>,
+    Fut: Future<Output = Result<Data<T>, E>>,
     F: FnOnce() -> Fut,
 >(
-    path: P,
+    path: &Path,
+    max_age: Duration,
     create: F,
-) -> Result<T, CacheError<E>> {
-    //TODO: make the file IO async in this file
-    fn cache_read<P: AsRef<Path>, T: DeserializeOwned + Serialize>(path: P) -> Option<T> {
-        let file = File::open(path).ok()?;
-        let reader = BufReader::new(file);
-        serde_json::from_reader(reader).ok()
-    }
-    match cache_read(&path) {
-        Some(x) => Ok(x),
-        None => {
-            let result = create().await.map_err(|x| CacheError::InnerError(x))?;
-            if let Some(parent) = path.as_ref().parent() {
-                std::fs::create_dir_all(parent).map_err(|x| CacheError::CreateFileError(x))?;
-            }
-            let file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(path)
-                .map_err(|x| CacheError::CreateFileError(x))?;
-            let writer = BufWriter::new(file);
-            serde_json::to_writer(writer, &result).map_err(|x| CacheError::SerdeError(x))?;
-            Ok(result)
-        }
+) -> Result<Cached<T>, CacheError<E>> {
+    if let Some(envelope) = cache_read(path).await {
+        return Ok(cached(envelope, max_age));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(CacheError::CreateFileError)?;
+    }
+    let _lock = CacheLock::acquire(path, true)
+        .await
+        .map_err(CacheError::CreateFileError)?;
+    if let Some(envelope) = read_envelope(path).await {
+        return Ok(cached(envelope, max_age));
     }
+    let data = create().await.map_err(CacheError::InnerError)?;
+    let envelope = envelope(data);
+    write_envelope(path, &envelope).await?;
+    Ok(cached(envelope, max_age))
 }
 
+const ITEM_IDENTIFIERS_FILE: &str = "item_identifiers_v2.json";
+const RELICS_FILE: &str = "relics_v2.json";
+const ITEMS_AND_SETS_FILE: &str = "items_and_sets_v2.json";
+const DROP_TABLES_FILE: &str = "drop_tables.json";
+
 pub async fn cached_get_item_identifiers(
     cache_path: &Path,
-) -> Result<Vec<ItemIdentifier>, CacheError<ReqwestSerdeError>> {
-    cache_in_file(cache_path.join("item_identifiers_v2.json"), || async {
-        get_item_identifiers().await
-    })
+    market: &MarketClient,
+    on_progress: OnProgress<'_>,
+) -> Result<Cached<Vec<ItemIdentifier>>, CacheError<ReqwestSerdeError>> {
+    cache_in_file(
+        &cache_path.join(ITEM_IDENTIFIERS_FILE),
+        ITEM_CACHE_MAX_AGE,
+        || async {
+            fetch_all(vec![get_item_identifiers(market)], on_progress)
+                .await
+                .map(|mut x| x.remove(0))
+        },
+    )
     .await
 }
 
 pub async fn cached_fetch_relics(
     cache_path: &Path,
-    item_identifiers: &Vec<ItemIdentifier>,
-) -> Result<Vec<Relic>, CacheError<ReqwestSerdeError>> {
-    cache_in_file(cache_path.join("relics_v2.json"), || async {
-        fetch_relics(item_identifiers).await
-    })
+    market: &MarketClient,
+    item_identifiers: &[ItemIdentifier],
+    on_progress: OnProgress<'_>,
+) -> Result<Cached<Vec<Relic>>, CacheError<ReqwestSerdeError>> {
+    cache_in_file(
+        &cache_path.join(RELICS_FILE),
+        ITEM_CACHE_MAX_AGE,
+        || async { fetch_relics(market, item_identifiers, on_progress).await },
+    )
     .await
 }
 
 pub async fn cached_items_and_sets(
     cache_path: &Path,
-    item_identifiers: &Vec<ItemIdentifier>,
+    market: &MarketClient,
+    item_identifiers: &[ItemIdentifier],
+    on_progress: OnProgress<'_>,
+) -> Result<Cached<(HashMap<String, Item>, HashMap<String, ItemSet>)>, CacheError<ReqwestSerdeError>>
+{
+    cache_in_file(
+        &cache_path.join(ITEMS_AND_SETS_FILE),
+        ITEM_CACHE_MAX_AGE,
+        || async { fetch_items_and_sets(market, item_identifiers, on_progress).await },
+    )
+    .await
+}
+
+/// Refetch every relic regardless of cache age and rewrite the cache file.
+pub async fn refresh_relics(
+    cache_path: &Path,
+    market: &MarketClient,
+    item_identifiers: &[ItemIdentifier],
+) -> Result<Vec<Relic>, CacheError<ReqwestSerdeError>> {
+    Ok(cache_refresh(&cache_path.join(RELICS_FILE), || {
+        fetch_relics(market, item_identifiers, &|_| {})
+    })
+    .await?
+    .data)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DropTableError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("serde error")]
+    Serde(#[from] serde_json::Error),
+    #[error("cache write error")]
+    Cache(#[from] CacheError<std::convert::Infallible>),
+}
+
+/// Drop tables as cached, with what they were resolved against.
+#[derive(Debug, Serialize, Deserialize)]
+struct ResolvedDropTables {
+    /// [`database_fingerprint`] of the items and relics
+    fingerprint: u64,
+    tables: DropTables,
+}
+
+/// changes whenever an item or relic comes, goes or is renamed, i.e. whenever
+/// slugs could resolve differently
+fn database_fingerprint(items: &HashMap<String, Item>, relics: &HashMap<String, Relic>) -> u64 {
+    let mut ids = items
+        .values()
+        .map(|x| (&x.id, &x.id_name))
+        .chain(relics.values().map(|x| (&x.id, &x.id_name)))
+        .collect::<Vec<_>>();
+    ids.sort();
+    let mut hasher = DefaultHasher::new();
+    ids.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Drop tables from the local drop-data file at `source` (format on
+/// [`DropTables`]), resolved against `items` and `relics` and cached next to
+/// the relics. The file is only parsed again once it changes, or once
+/// `items` and `relics` do, so parts and relics it names that weren't known
+/// before are picked up.
+pub async fn cached_drop_tables(
+    cache_path: &Path,
+    source: &Path,
+    items: &HashMap<String, Item>,
+    relics: &HashMap<String, Relic>,
+) -> Result<DropTables, DropTableError> {
+    let path = cache_path.join(DROP_TABLES_FILE);
+    let modified = DateTime::<Utc>::from(fs::metadata(source).await?.modified()?);
+    let fingerprint = database_fingerprint(items, relics);
+    if let Some(cached) = cache_read::<ResolvedDropTables>(&path).await {
+        if cached.fetched_at >= modified && cached.data.fingerprint == fingerprint {
+            return Ok(cached.data.tables);
+        }
+    }
+    let envelope = CacheEnvelope {
+        schema_version: CACHE_SCHEMA_VERSION,
+        fetched_at: Utc::now(),
+        api_version: None,
+        data: ResolvedDropTables {
+            fingerprint,
+            tables: DropTables::from_json(&fs::read(source).await?, items, relics)?,
+        },
+    };
+    cache_write(&path, &envelope).await?;
+    Ok(envelope.data.tables)
+}
+
+/// Refetch the item list regardless of cache age and bring the cached sets up
+/// to date with it, rewriting both cache files. Only new or changed sets are
+/// requested when both caches are readable; otherwise everything is. The item
+/// list is only written once the sets are, so a failed update is retried in
+/// full next time instead of diffed against a list it never caught up with.
+pub async fn refresh_items_and_sets(
+    cache_path: &Path,
+    market: &MarketClient,
 ) -> Result<(HashMap<String, Item>, HashMap<String, ItemSet>), CacheError<ReqwestSerdeError>> {
-    cache_in_file(cache_path.join("items_and_sets_v2.json"), || async {
-        fetch_items_and_sets(item_identifiers).await
+    let identifiers_path = cache_path.join(ITEM_IDENTIFIERS_FILE);
+    let items_and_sets_path = cache_path.join(ITEMS_AND_SETS_FILE);
+    let previous_identifiers = cache_read::<Vec<ItemIdentifier>>(&identifiers_path).await;
+    let previous_items_and_sets = cache_read(&items_and_sets_path).await;
+    let previous = previous_identifiers.zip(previous_items_and_sets);
+    let item_identifiers = get_item_identifiers(market)
+        .await
+        .map_err(CacheError::InnerError)?;
+    let items_and_sets = cache_refresh(&items_and_sets_path, || async {
+        match previous {
+            Some((previous, cached)) => {
+                let (items, sets) = cached.data;
+                let updated = update_items_and_sets(
+                    market,
+                    &previous.data,
+                    &item_identifiers.data,
+                    items,
+                    sets,
+                    &|_| {},
+                )
+                .await?;
+                // nothing to fetch is still up to date with the item list
+                Ok(Data {
+                    api_version: updated
+                        .api_version
+                        .or_else(|| item_identifiers.api_version.clone()),
+                    data: updated.data,
+                })
+            }
+            None => fetch_items_and_sets(market, &item_identifiers.data, &|_| {}).await,
+        }
     })
-    .await
+    .await?
+    .data;
+    cache_write(&identifiers_path, &envelope(item_identifiers)).await?;
+    Ok(items_and_sets)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use cephalon_rust_mock_market::{fixtures_dir, spawn_fixtures, MOCK_REQUESTS_PER_SECOND};
+
+    use relics::fetch_relics;
+
+    use crate::test_support::{mock_database, mock_market, MockDatabase};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_set_fetch_progress() {
+        let market = mock_market().await;
+        let identifiers = get_item_identifiers(&market).await.unwrap().data;
+        let reports = Mutex::new(Vec::new());
+        let (items, sets) =
+            fetch_items_and_sets(&market, &identifiers, &|x| reports.lock().unwrap().push(x))
+                .await
+                .unwrap()
+                .data;
+        let reports = reports.into_inner().unwrap();
+
+        // one request per prime slug, set roots included
+        let total = items.len() + sets.len();
+        assert_eq!(reports.len(), total + 1);
+        for (done, report) in reports.iter().enumerate() {
+            assert_eq!(
+                *report,
+                FetchProgress {
+                    done,
+                    failed: 0,
+                    total
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_failures_and_still_finishes() {
+        let reports = Mutex::new(Vec::new());
+        let requests = (0..4)
+            .map(|i| async move {
+                match i % 2 {
+                    0 => Ok(i),
+                    _ => Err(serde_json::from_str::<u32>("").unwrap_err().into()),
+                }
+            })
+            .collect();
+        let result = fetch_all(requests, &|x| reports.lock().unwrap().push(x)).await;
+        assert!(result.is_err());
+        assert_eq!(
+            reports.into_inner().unwrap().last(),
+            Some(&FetchProgress {
+                done: 4,
+                failed: 2,
+                total: 4
+            })
+        );
+    }
+
+    async fn never() -> Result<Data<Vec<u32>>, std::io::Error> {
+        panic!("cache hit expected")
+    }
+
+    async fn fetched() -> Result<Data<Vec<u32>>, std::io::Error> {
+        Ok(Data {
+            api_version: Some("0.22.7".to_string()),
+            data: vec![1, 2, 3],
+        })
+    }
+
+    #[tokio::test]
+    async fn fresh_cache_is_not_refetched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let first = cache_in_file(&path, ITEM_CACHE_MAX_AGE, fetched)
+            .await
+            .unwrap();
+        assert!(!first.stale);
+        let second = cache_in_file(&path, ITEM_CACHE_MAX_AGE, never)
+            .await
+            .unwrap();
+        assert_eq!(second.data, vec![1, 2, 3]);
+        assert_eq!(second.fetched_at, first.fetched_at);
+        // stamped with the version the fetch came back with
+        let written = read_envelope::<Vec<u32>>(&path).await.unwrap();
+        assert_eq!(written.api_version.as_deref(), Some("0.22.7"));
+    }
+
+    #[tokio::test]
+    async fn old_cache_is_served_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let envelope = CacheEnvelope {
+            schema_version: CACHE_SCHEMA_VERSION,
+            fetched_at: Utc::now() - chrono::Duration::days(2),
+            api_version: Some("0.22.7".to_string()),
+            data: vec![4u32],
+        };
+        std::fs::write(&path, serde_json::to_vec(&envelope).unwrap()).unwrap();
+        let cached = cache_in_file(&path, ITEM_CACHE_MAX_AGE, never)
+            .await
+            .unwrap();
+        assert!(cached.stale);
+        assert_eq!(cached.data, vec![4]);
+    }
+
+    #[tokio::test]
+    async fn other_schemas_are_refetched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        for contents in [
+            // pre-versioning files were the bare data
+            "[7, 8]",
+            r#"{"schema_version": 0, "fetched_at": "2024-01-01T00:00:00Z", "data": [7]}"#,
+            "{not json",
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            let cached = cache_in_file(&path, ITEM_CACHE_MAX_AGE, fetched)
+                .await
+                .unwrap();
+            assert_eq!(cached.data, vec![1, 2, 3], "{contents}");
+            let written: CacheEnvelope<Vec<u32>> =
+                serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            assert_eq!(written.schema_version, CACHE_SCHEMA_VERSION);
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_fetch_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let calls = AtomicUsize::new(0);
+        let create = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            fetched().await
+        };
+        let (a, b) = tokio::join!(
+            cache_in_file(&path, ITEM_CACHE_MAX_AGE, create),
+            cache_in_file(&path, ITEM_CACHE_MAX_AGE, create),
+        );
+        assert_eq!(a.unwrap().data, b.unwrap().data);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn writes_leave_no_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        cache_in_file(&path, ITEM_CACHE_MAX_AGE, fetched)
+            .await
+            .unwrap();
+        cache_refresh(&path, fetched).await.unwrap();
+        let mut names = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|x| x.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["cache.json", "cache.json.lock"]);
+    }
+
+    #[tokio::test]
+    async fn failed_set_update_is_retried() {
+        let MockDatabase {
+            market,
+            mut identifiers,
+            mut items,
+            mut sets,
+        } = mock_database().await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path();
+        // cached before okina prime came out
+        let is_okina = |x: &ItemIdentifier| matches!(x, ItemIdentifier::Item { id_name } if id_name.starts_with("okina_prime"));
+        identifiers.retain(|x| !is_okina(x));
+        items.retain(|_, x| !x.id_name.starts_with("okina_prime"));
+        sets.retain(|_, x| x.id_name != "okina_prime_set");
+        cache_write::<_, std::io::Error>(
+            &cache.join(ITEM_IDENTIFIERS_FILE),
+            &envelope(Data {
+                api_version: None,
+                data: identifiers,
+            }),
+        )
+        .await
+        .unwrap();
+        cache_write::<_, std::io::Error>(
+            &cache.join(ITEMS_AND_SETS_FILE),
+            &envelope(Data {
+                api_version: None,
+                data: (items, sets),
+            }),
+        )
+        .await
+        .unwrap();
+
+        // the item list comes through, every set request fails
+        let fixtures = tempfile::tempdir().unwrap();
+        std::fs::copy(
+            fixtures_dir().join("items.json"),
+            fixtures.path().join("items.json"),
+        )
+        .unwrap();
+        for x in ["sets", "relics", "orders"] {
+            std::fs::create_dir(fixtures.path().join(x)).unwrap();
+        }
+        let failing = MarketClient::new(
+            spawn_fixtures(fixtures.path()).await,
+            MOCK_REQUESTS_PER_SECOND,
+        );
+        assert!(refresh_items_and_sets(cache, &failing).await.is_err());
+        let cached = cache_read::<Vec<ItemIdentifier>>(&cache.join(ITEM_IDENTIFIERS_FILE))
+            .await
+            .unwrap();
+        assert!(!cached.data.iter().any(is_okina));
+
+        let (items, _) = refresh_items_and_sets(cache, &market).await.unwrap();
+        assert!(items.values().any(|x| x.id_name == "okina_prime_blade"));
+    }
+
+    #[tokio::test]
+    async fn drop_tables_follow_the_database() {
+        let MockDatabase {
+            market,
+            identifiers,
+            items,
+            ..
+        } = mock_database().await;
+        let relics = fetch_relics(&market, &identifiers, &|_| {})
+            .await
+            .unwrap()
+            .data
+            .into_iter()
+            .map(|x| (x.id.clone(), x))
+            .collect::<HashMap<_, _>>();
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("drops.json");
+        std::fs::write(
+            &source,
+            r#"{ "lith_b4_relic": { "rare": ["okina_prime_handle"] } }"#,
+        )
+        .unwrap();
+        let lith = relics
+            .values()
+            .find(|x| x.id_name == "lith_b4_relic")
+            .unwrap();
+
+        // resolved before okina prime was in the database
+        let mut old_items = items.clone();
+        old_items.retain(|_, x| !x.id_name.starts_with("okina_prime"));
+        let tables = cached_drop_tables(dir.path(), &source, &old_items, &relics)
+            .await
+            .unwrap();
+        assert!(tables.for_relic(&lith.id).unwrap().rare.is_empty());
+
+        // the file didn't change, the database did
+        let tables = cached_drop_tables(dir.path(), &source, &items, &relics)
+            .await
+            .unwrap();
+        assert_eq!(tables.for_relic(&lith.id).unwrap().rare.len(), 1);
+    }
 }
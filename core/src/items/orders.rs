@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{config::client, items::Data};
+use crate::config::MarketClient;
 
 use super::ReqwestSerdeError;
 
@@ -51,26 +51,25 @@ pub struct Order {
     pub user: User,
 }
 
-pub async fn fetch_orders(id_name: &str) -> Result<Vec<Order>, ReqwestSerdeError> {
-    Ok(client()
-        .await
-        .get(format!(
-            "https://api.warframe.market/v2/orders/item/{id_name}"
-        ))
-        .send()
-        .await?
-        .json::<Data<Vec<Order>>>()
+pub async fn fetch_orders(
+    market: &MarketClient,
+    id_name: &str,
+) -> Result<Vec<Order>, ReqwestSerdeError> {
+    Ok(market
+        .get_data(&format!("/v2/orders/item/{id_name}"))
         .await?
         .data)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::items::orders::fetch_orders;
+    use crate::{items::orders::fetch_orders, test_support::mock_market};
 
     #[tokio::test]
     async fn it_works() {
-        let result = fetch_orders("saryn_prime_systems_blueprint").await;
+        let market = mock_market().await;
+        let result = fetch_orders(&market, "saryn_prime_systems_blueprint").await;
         assert!(result.is_ok(), "{result:?}");
+        assert!(!result.unwrap().is_empty());
     }
 }
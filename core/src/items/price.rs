@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use super::orders::{Order, OrderType, Platform, UserStatus};
+
+/// Turns the full order book of one item into a single platinum estimate.
+/// `orders` is everything warframe.market returned, unfiltered — platform,
+/// locale and online-status policy is up to the strategy.
+pub trait PriceStrategy: Send + Sync {
+    fn price(&self, orders: &[Order]) -> u32;
+}
+
+/// PC orders from english-locale users of the given type. When more than 3 of
+/// those users are online, offline users are dropped; with fewer the offline
+/// ones are kept so thin markets still produce a number.
+fn relevant_prices(orders: &[Order], order_type: OrderType) -> Vec<u32> {
+    let orders = orders
+        .iter()
+        .filter(|x| {
+            x.user.platform == Platform::Pc && x.user.locale == "en" && x.order_type == order_type
+        })
+        .collect::<Vec<_>>();
+    let online = orders
+        .iter()
+        .filter(|x| x.user.status != UserStatus::Offline)
+        .count();
+    let mut prices = orders
+        .into_iter()
+        .filter(|x| online <= 3 || x.user.status != UserStatus::Offline)
+        .map(|x| x.platinum)
+        .collect::<Vec<_>>();
+    prices.sort();
+    prices
+}
+
+/// median buy order; the original (and default) policy
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Median;
+
+impl PriceStrategy for Median {
+    fn price(&self, orders: &[Order]) -> u32 {
+        let prices = relevant_prices(orders, OrderType::Buy);
+        prices.get(prices.len() / 2).copied().unwrap_or(0)
+    }
+}
+
+/// cheapest sell order — what you'd have to undercut to sell right now
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowestSell;
+
+impl PriceStrategy for LowestSell {
+    fn price(&self, orders: &[Order]) -> u32 {
+        relevant_prices(orders, OrderType::Sell)
+            .first()
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// best buy order — what you'd get dumping the item immediately
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HighestBuy;
+
+impl PriceStrategy for HighestBuy {
+    fn price(&self, orders: &[Order]) -> u32 {
+        relevant_prices(orders, OrderType::Buy)
+            .last()
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// mean of the buy orders after cutting `fraction` off each end, so a single
+/// troll order at 1p or 9999p doesn't drag the estimate
+#[derive(Debug, Clone, Copy)]
+pub struct TrimmedMean {
+    /// share of orders dropped from each end, clamped to `0.0..=0.49` so
+    /// at least one is kept
+    pub fraction: f64,
+}
+
+impl Default for TrimmedMean {
+    fn default() -> Self {
+        Self { fraction: 0.2 }
+    }
+}
+
+impl PriceStrategy for TrimmedMean {
+    fn price(&self, orders: &[Order]) -> u32 {
+        let prices = relevant_prices(orders, OrderType::Buy);
+        let cut = (prices.len() as f64 * self.fraction.clamp(0.0, 0.49)) as usize;
+        let kept = &prices[cut..prices.len() - cut];
+        if kept.is_empty() {
+            return 0;
+        }
+        let sum = kept.iter().map(|&x| x as u64).sum::<u64>();
+        (sum as f64 / kept.len() as f64).round() as u32
+    }
+}
+
+/// point between the highest buy and the lowest sell order. `buy_weight` 1.0
+/// is [`HighestBuy`], 0.0 is [`LowestSell`]. falls back to whichever side
+/// exists when the other is empty
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedMid {
+    pub buy_weight: f64,
+}
+
+impl Default for WeightedMid {
+    fn default() -> Self {
+        Self { buy_weight: 0.5 }
+    }
+}
+
+impl PriceStrategy for WeightedMid {
+    fn price(&self, orders: &[Order]) -> u32 {
+        let buy = relevant_prices(orders, OrderType::Buy).last().copied();
+        let sell = relevant_prices(orders, OrderType::Sell).first().copied();
+        match (buy, sell) {
+            (Some(buy), Some(sell)) => {
+                let w = self.buy_weight.clamp(0.0, 1.0);
+                (buy as f64 * w + sell as f64 * (1.0 - w)).round() as u32
+            }
+            (Some(x), None) | (None, Some(x)) => x,
+            (None, None) => 0,
+        }
+    }
+}
+
+/// look up a built-in strategy (with default parameters) by its config name:
+/// `median`, `lowest_sell`, `highest_buy`, `trimmed_mean` or `weighted_mid`
+pub fn price_strategy_by_name(name: &str) -> Option<Arc<dyn PriceStrategy>> {
+    Some(match name {
+        "median" => Arc::new(Median),
+        "lowest_sell" => Arc::new(LowestSell),
+        "highest_buy" => Arc::new(HighestBuy),
+        "trimmed_mean" => Arc::new(TrimmedMean::default()),
+        "weighted_mid" => Arc::new(WeightedMid::default()),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::items::orders::User;
+
+    use super::*;
+
+    fn order(platinum: u32, order_type: OrderType, status: UserStatus) -> Order {
+        Order {
+            platinum,
+            order_type,
+            user: User {
+                platform: Platform::Pc,
+                locale: "en".to_string(),
+                status,
+            },
+        }
+    }
+
+    fn book() -> Vec<Order> {
+        vec![
+            order(1, OrderType::Buy, UserStatus::Ingame),
+            order(10, OrderType::Buy, UserStatus::Ingame),
+            order(12, OrderType::Buy, UserStatus::Online),
+            order(14, OrderType::Buy, UserStatus::Online),
+            order(90, OrderType::Buy, UserStatus::Ingame),
+            order(500, OrderType::Buy, UserStatus::Offline),
+            order(20, OrderType::Sell, UserStatus::Ingame),
+            order(16, OrderType::Sell, UserStatus::Online),
+            order(2, OrderType::Sell, UserStatus::Offline),
+        ]
+    }
+
+    #[test]
+    fn median_drops_offline_when_enough_online() {
+        assert_eq!(Median.price(&book()), 12);
+    }
+
+    #[test]
+    fn median_keeps_offline_in_thin_markets() {
+        let orders = vec![
+            order(5, OrderType::Buy, UserStatus::Offline),
+            order(7, OrderType::Buy, UserStatus::Offline),
+            order(9, OrderType::Buy, UserStatus::Ingame),
+        ];
+        assert_eq!(Median.price(&orders), 7);
+    }
+
+    #[test]
+    fn ignores_other_platforms_and_locales() {
+        let mut orders = vec![order(3, OrderType::Buy, UserStatus::Ingame)];
+        let mut xbox = order(100, OrderType::Buy, UserStatus::Ingame);
+        xbox.user.platform = Platform::Xbox;
+        let mut german = order(100, OrderType::Buy, UserStatus::Ingame);
+        german.user.locale = "de".to_string();
+        orders.extend([xbox, german]);
+        assert_eq!(HighestBuy.price(&orders), 3);
+    }
+
+    #[test]
+    fn sides() {
+        assert_eq!(LowestSell.price(&book()), 2);
+        // plenty of online buyers, so the offline 500p order is ignored
+        assert_eq!(HighestBuy.price(&book()), 90);
+    }
+
+    #[test]
+    fn trimmed_mean_cuts_outliers() {
+        // online buys: 1, 10, 12, 14, 90 -> trim one each end -> 10, 12, 14
+        assert_eq!(TrimmedMean::default().price(&book()), 12);
+        assert_eq!(TrimmedMean { fraction: 0.0 }.price(&book()), 25);
+        // cutting half or more would leave nothing, the median is kept
+        assert_eq!(TrimmedMean { fraction: 0.9 }.price(&book()), 12);
+    }
+
+    #[test]
+    fn weighted_mid() {
+        let orders = vec![
+            order(10, OrderType::Buy, UserStatus::Ingame),
+            order(20, OrderType::Sell, UserStatus::Ingame),
+        ];
+        assert_eq!(WeightedMid::default().price(&orders), 15);
+        assert_eq!(WeightedMid { buy_weight: 1.0 }.price(&orders), 10);
+        assert_eq!(WeightedMid::default().price(&orders[..1]), 10);
+    }
+
+    #[test]
+    fn empty_book_is_zero() {
+        for name in [
+            "median",
+            "lowest_sell",
+            "highest_buy",
+            "trimmed_mean",
+            "weighted_mid",
+        ] {
+            assert_eq!(
+                price_strategy_by_name(name).unwrap().price(&[]),
+                0,
+                "{name}"
+            );
+        }
+        assert!(price_strategy_by_name("nope").is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::OnceCell, time::Instant};
+
+use crate::config::MarketClient;
+
+use super::{
+    items::Item,
+    orders::{fetch_orders, Order},
+    price::PriceStrategy,
+    ReqwestSerdeError,
+};
+
+/// how long a fetched order book is trusted before it is fetched again
+pub const DEFAULT_PRICE_TTL: Duration = Duration::from_secs(5 * 60);
+
+struct CachedOrders {
+    fetched: Instant,
+    orders: Arc<Vec<Order>>,
+}
+
+/// Order books keyed by item slug, shared across reward sessions. Concurrent
+/// lookups of the same slug wait on one in-flight fetch instead of each hitting
+/// warframe.market; failed fetches aren't cached so the next lookup retries.
+pub struct PriceCache {
+    market: MarketClient,
+    strategy: Arc<dyn PriceStrategy>,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Arc<OnceCell<CachedOrders>>>>,
+}
+
+impl PriceCache {
+    pub fn new(market: MarketClient, strategy: Arc<dyn PriceStrategy>, ttl: Duration) -> Self {
+        Self {
+            market,
+            strategy,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// price `item` with the cache's strategy, fetching its orders if needed
+    pub async fn price(&self, item: &Item) -> Result<u32, ReqwestSerdeError> {
+        self.price_of(&item.id_name).await
+    }
+
+    /// like [`PriceCache::price`] but by slug, for things that aren't an
+    /// [`Item`] such as whole sets
+    pub async fn price_of(&self, id_name: &str) -> Result<u32, ReqwestSerdeError> {
+        let orders = self.orders(id_name).await?;
+        Ok(self.strategy.price(&orders))
+    }
+
+    pub async fn orders(&self, id_name: &str) -> Result<Arc<Vec<Order>>, ReqwestSerdeError> {
+        self.orders_with(id_name, |id_name| fetch_orders(&self.market, id_name))
+            .await
+    }
+
+    async fn orders_with<'a, F, Fut>(
+        &self,
+        id_name: &'a str,
+        fetch: F,
+    ) -> Result<Arc<Vec<Order>>, ReqwestSerdeError>
+    where
+        F: FnOnce(&'a str) -> Fut,
+        Fut: Future<Output = Result<Vec<Order>, ReqwestSerdeError>>,
+    {
+        let cell = {
+            let mut entries = self.entries.lock().unwrap();
+            let cell = entries.entry(id_name.to_string()).or_default();
+            if cell.get().is_some_and(|x| x.fetched.elapsed() >= self.ttl) {
+                *cell = Arc::default();
+            }
+            cell.clone()
+        };
+        let cached = cell
+            .get_or_try_init(|| async {
+                Ok::<_, ReqwestSerdeError>(CachedOrders {
+                    orders: Arc::new(fetch(id_name).await?),
+                    fetched: Instant::now(),
+                })
+            })
+            .await?;
+        Ok(cached.orders.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future::join_all;
+    use tokio::time::{advance, sleep};
+
+    use crate::items::price::Median;
+
+    use super::*;
+
+    async fn counting_fetch(counter: &AtomicUsize) -> Result<Vec<Order>, ReqwestSerdeError> {
+        counter.fetch_add(1, Ordering::SeqCst);
+        // keep the fetch in flight long enough for the other lookups to pile up
+        sleep(Duration::from_millis(50)).await;
+        Ok(Vec::new())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dedups_in_flight_fetches() {
+        let cache = PriceCache::new(MarketClient::default(), Arc::new(Median), DEFAULT_PRICE_TTL);
+        let fetches = AtomicUsize::new(0);
+        join_all(
+            (0..8).map(|_| cache.orders_with("lex_prime_receiver", |_| counting_fetch(&fetches))),
+        )
+        .await
+        .into_iter()
+        .for_each(|x| assert!(x.is_ok()));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        cache
+            .orders_with("lex_prime_barrel", |_| counting_fetch(&fetches))
+            .await
+            .unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refetches_after_ttl() {
+        let cache = PriceCache::new(
+            MarketClient::default(),
+            Arc::new(Median),
+            Duration::from_secs(60),
+        );
+        let fetches = AtomicUsize::new(0);
+        let get = || cache.orders_with("lex_prime_receiver", |_| counting_fetch(&fetches));
+        get().await.unwrap();
+        advance(Duration::from_secs(30)).await;
+        get().await.unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        advance(Duration::from_secs(31)).await;
+        get().await.unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+}
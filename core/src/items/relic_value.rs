@@ -0,0 +1,266 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use futures::future::join_all;
+use tracing::*;
+
+use super::{
+    drop_tables::{DropEntry, Rarity, RelicDropTable},
+    items::ItemDatabase,
+    price_cache::PriceCache,
+    relics::Relic,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Refinement {
+    Intact,
+    Exceptional,
+    Flawless,
+    Radiant,
+}
+
+impl Refinement {
+    pub const ALL: [Refinement; 4] = [
+        Refinement::Intact,
+        Refinement::Exceptional,
+        Refinement::Flawless,
+        Refinement::Radiant,
+    ];
+
+    /// the standard chance of each single entry of that rarity dropping
+    pub fn chance(self, rarity: Rarity) -> f64 {
+        match (self, rarity) {
+            (Refinement::Intact, Rarity::Common) => 0.2533,
+            (Refinement::Intact, Rarity::Uncommon) => 0.11,
+            (Refinement::Intact, Rarity::Rare) => 0.02,
+            (Refinement::Exceptional, Rarity::Common) => 0.2333,
+            (Refinement::Exceptional, Rarity::Uncommon) => 0.13,
+            (Refinement::Exceptional, Rarity::Rare) => 0.04,
+            (Refinement::Flawless, Rarity::Common) => 0.20,
+            (Refinement::Flawless, Rarity::Uncommon) => 0.17,
+            (Refinement::Flawless, Rarity::Rare) => 0.06,
+            (Refinement::Radiant, Rarity::Common) => 0.1667,
+            (Refinement::Radiant, Rarity::Uncommon) => 0.20,
+            (Refinement::Radiant, Rarity::Rare) => 0.10,
+        }
+    }
+}
+
+impl Display for Refinement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refinement::Intact => write!(f, "intact"),
+            Refinement::Exceptional => write!(f, "exceptional"),
+            Refinement::Flawless => write!(f, "flawless"),
+            Refinement::Radiant => write!(f, "radiant"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown refinement {0:?}")]
+pub struct UnknownRefinement(String);
+
+impl FromStr for Refinement {
+    type Err = UnknownRefinement;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Refinement::ALL
+            .into_iter()
+            .find(|x| x.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownRefinement(s.to_string()))
+    }
+}
+
+/// What cracking one relic is worth to one player of the squad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelicValue {
+    pub refinement: Refinement,
+    /// expected platinum when everyone picks the most valuable reward
+    pub plat: f64,
+    /// expected ducats when everyone picks the one worth most ducats
+    pub ducats: f64,
+}
+
+/// Expected best of `squad` independent rolls from `outcomes`, given as
+/// `(chance, value)`. Chances are normalized, so tables that don't add up to
+/// exactly 1 (rounding, odd entry counts) still work.
+///
+/// With `F(v)` the chance one roll is worth at most `v`, the best of n rolls
+/// is worth at most `v` with chance `F(v)^n`; summing each value times the
+/// step that makes in the cdf gives the expectation.
+pub fn expected_best_of(outcomes: &[(f64, f64)], squad: u32) -> f64 {
+    let total = outcomes.iter().map(|(chance, _)| chance).sum::<f64>();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let mut outcomes = outcomes.to_vec();
+    outcomes.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let squad = squad.max(1) as i32;
+    let mut cdf = 0.0;
+    let mut expected = 0.0;
+    for (chance, value) in outcomes {
+        let next = (cdf + chance / total).min(1.0);
+        expected += value * (next.powi(squad) - cdf.powi(squad));
+        cdf = next;
+    }
+    expected
+}
+
+/// [`RelicValue`] at every refinement, in [`Refinement::ALL`] order. `plat`
+/// is keyed by item id; `None` if a part of the relic has no price there.
+/// Forma is worth nothing either way.
+pub fn relic_values(
+    table: &RelicDropTable,
+    db: &ItemDatabase,
+    plat: &HashMap<String, u32>,
+    squad: u32,
+) -> Option<[RelicValue; 4]> {
+    let entries = table
+        .entries()
+        .map(|(rarity, entry)| match entry {
+            DropEntry::Forma => Some((rarity, 0.0, 0.0)),
+            DropEntry::Item(id) => {
+                let item = db.items.get(id)?;
+                Some((rarity, *plat.get(id)? as f64, item.ducats as f64))
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(Refinement::ALL.map(|refinement| {
+        let outcomes = |value: fn(&(Rarity, f64, f64)) -> f64| {
+            entries
+                .iter()
+                .map(|x| (refinement.chance(x.0), value(x)))
+                .collect::<Vec<_>>()
+        };
+        RelicValue {
+            refinement,
+            plat: expected_best_of(&outcomes(|x| x.1), squad),
+            ducats: expected_best_of(&outcomes(|x| x.2), squad),
+        }
+    }))
+}
+
+/// Prices of every part `tables` drop, keyed by item id. Parts whose lookup
+/// failed are left out and logged.
+pub async fn drop_prices<'a>(
+    tables: impl IntoIterator<Item = &'a RelicDropTable>,
+    db: &ItemDatabase,
+    prices: &PriceCache,
+) -> HashMap<String, u32> {
+    let mut items = tables
+        .into_iter()
+        .flat_map(|x| x.items(db))
+        .map(|(_, item)| item)
+        .collect::<Vec<_>>();
+    items.sort_by(|a, b| a.id.cmp(&b.id));
+    items.dedup_by(|a, b| a.id == b.id);
+    join_all(items.into_iter().map(|item| async move {
+        match prices.price(item).await {
+            Ok(price) => Some((item.id.clone(), price)),
+            Err(e) => {
+                event!(Level::WARN, "no price for {}: {e}", item.id_name);
+                None
+            }
+        }
+    }))
+    .await
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Every relic with a drop table, best expected platinum at `refinement`
+/// first. Relics with a part that couldn't be priced are left out.
+pub async fn rank_relics<'a>(
+    db: &'a ItemDatabase,
+    prices: &PriceCache,
+    refinement: Refinement,
+    squad: u32,
+) -> Vec<(&'a Relic, RelicValue)> {
+    let tables = db
+        .relics
+        .values()
+        .flat_map(|relic| Some((relic, db.drop_tables.for_relic(&relic.id)?)))
+        .collect::<Vec<_>>();
+    let plat = drop_prices(tables.iter().map(|x| x.1), db, prices).await;
+    let mut ranked = tables
+        .into_iter()
+        .flat_map(|(relic, table)| {
+            let values = relic_values(table, db, &plat, squad)?;
+            Some((
+                relic,
+                values.into_iter().find(|x| x.refinement == refinement)?,
+            ))
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.plat.total_cmp(&a.1.plat));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_player_is_the_plain_mean() {
+        let outcomes = [(0.5, 10.0), (0.5, 30.0)];
+        assert!((expected_best_of(&outcomes, 1) - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn best_of_n() {
+        // only misses the 30 if every roll misses it: 0.5^4
+        let outcomes = [(0.5, 10.0), (0.5, 30.0)];
+        let expected = 10.0 * 0.0625 + 30.0 * (1.0 - 0.0625);
+        assert!((expected_best_of(&outcomes, 4) - expected).abs() < 1e-9);
+        assert_eq!(expected_best_of(&[], 4), 0.0);
+    }
+
+    #[test]
+    fn standard_chances_add_up() {
+        for refinement in Refinement::ALL {
+            let total = 3.0 * refinement.chance(Rarity::Common)
+                + 2.0 * refinement.chance(Rarity::Uncommon)
+                + refinement.chance(Rarity::Rare);
+            assert!((total - 1.0).abs() < 1e-3, "{refinement}: {total}");
+        }
+        assert_eq!(
+            "Radiant".parse::<Refinement>().unwrap(),
+            Refinement::Radiant
+        );
+        assert!("shiny".parse::<Refinement>().is_err());
+    }
+
+    #[test]
+    fn radiant_favours_the_rare() {
+        let table = RelicDropTable {
+            relic_id: "relic".to_string(),
+            common: vec![DropEntry::Forma; 3],
+            uncommon: vec![DropEntry::Forma; 2],
+            rare: vec![DropEntry::Item("rare".to_string())],
+        };
+        let mut db = ItemDatabase::default();
+        db.items.insert(
+            "rare".to_string(),
+            crate::items::items::Item {
+                id: "rare".to_string(),
+                id_name: "rare_prime_part".to_string(),
+                name: "Rare Prime Part".to_string(),
+                trading_tax: 0,
+                set_id: String::new(),
+                ducats: 100,
+                quantity_for_set: 1,
+            },
+        );
+        let plat = HashMap::from([("rare".to_string(), 50)]);
+        let [intact, _, _, radiant] = relic_values(&table, &db, &plat, 1).unwrap();
+        // the standard chances are rounded, so not exactly 2% and 10%
+        assert!((intact.plat - 1.0).abs() < 1e-3);
+        assert!((radiant.plat - 5.0).abs() < 1e-3);
+        assert!((radiant.ducats - 10.0).abs() < 1e-3);
+        // a radshare gets four shots at it
+        let [_, _, _, shared] = relic_values(&table, &db, &plat, 4).unwrap();
+        assert!((shared.plat - 50.0 * (1.0 - 0.9f64.powi(4))).abs() < 1e-2);
+        assert!(relic_values(&table, &db, &HashMap::new(), 1).is_none());
+    }
+}
@@ -1,11 +1,12 @@
-use futures::stream::{FuturesUnordered, StreamExt};
 use serde::{Deserialize, Serialize};
 
-use crate::{config::client, items::Data};
+use crate::config::MarketClient;
 
-use super::{item_identifiers::ItemIdentifier, I18n, ReqwestSerdeError};
+use super::{
+    fetch_all, item_identifiers::ItemIdentifier, Data, I18n, OnProgress, ReqwestSerdeError,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Relic {
     pub id: String,
     pub id_name: String,
@@ -15,7 +16,11 @@ pub struct Relic {
     pub trading_tax: u32,
 }
 
-pub async fn fetch_relics(identifiers: &[ItemIdentifier]) -> Result<Vec<Relic>, ReqwestSerdeError> {
+pub async fn fetch_relics(
+    market: &MarketClient,
+    identifiers: &[ItemIdentifier],
+    on_progress: OnProgress<'_>,
+) -> Result<Data<Vec<Relic>>, ReqwestSerdeError> {
     #[derive(Debug, Serialize, Deserialize)]
     struct Message {
         id: String,
@@ -28,40 +33,34 @@ pub async fn fetch_relics(identifiers: &[ItemIdentifier]) -> Result<Vec<Relic>,
         trading_tax: u32,
         i18n: I18n,
     }
-    let relics = identifiers
+    let requests = identifiers
         .iter()
         .flat_map(|x| match x {
             ItemIdentifier::Relic { id_name } => Some(id_name),
             ItemIdentifier::Item { id_name: _ } => None,
         })
         .map(|name| async move {
-            Ok(client()
+            market
+                .get_data::<Message>(&format!("/v2/item/{name}"))
                 .await
-                .get(format!("https://api.warframe.market/v2/item/{name}"))
-                .send()
-                .await?
-                .json::<Data<Message>>()
-                .await?
-                .data)
         })
-        .collect::<FuturesUnordered<_>>()
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .collect::<Result<Vec<_>, ReqwestSerdeError>>()?;
-    Ok(relics
-        .into_iter()
-        .flat_map(|x| {
-            // Relic tags look like ["relic", "lith"]; the era is the non-"relic" tag.
-            let era = x.tags.into_iter().find(|t| t != "relic")?;
-            Some(Relic {
-                id: x.id,
-                id_name: x.slug,
-                vaulted: x.vaulted,
-                era,
-                trading_tax: x.trading_tax,
-                name: x.i18n.en.name,
+        .collect::<Vec<_>>();
+    let relics = fetch_all(requests, on_progress).await?;
+    Ok(relics.into_iter().collect::<Data<Vec<_>>>().map(|relics| {
+        relics
+            .into_iter()
+            .flat_map(|x| {
+                // Relic tags look like ["relic", "lith"]; the era is the non-"relic" tag.
+                let era = x.tags.into_iter().find(|t| t != "relic")?;
+                Some(Relic {
+                    id: x.id,
+                    id_name: x.slug,
+                    vaulted: x.vaulted,
+                    era,
+                    trading_tax: x.trading_tax,
+                    name: x.i18n.en.name,
+                })
             })
-        })
-        .collect::<Vec<_>>())
+            .collect()
+    }))
 }
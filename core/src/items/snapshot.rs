@@ -0,0 +1,225 @@
+use std::{collections::HashMap, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::*;
+
+use super::{
+    cache_read, item_identifiers::ItemIdentifier, items::Item, items::ItemSet, read_envelope,
+    relics::Relic, write_atomic, write_envelope, CacheEnvelope, CacheError, CacheLock,
+    CACHE_SCHEMA_VERSION, ITEMS_AND_SETS_FILE, ITEM_IDENTIFIERS_FILE, RELICS_FILE,
+};
+
+/// Bump whenever [`Snapshot`]'s shape changes.
+pub const SNAPSHOT_SCHEMA_VERSION: u32 = 1;
+
+/// compiled in with the `bundled-snapshot` feature, from the file the
+/// `CEPHALON_SNAPSHOT` env var points at during the build
+#[cfg(feature = "bundled-snapshot")]
+const BUNDLED: &[u8] = include_bytes!(env!("CEPHALON_SNAPSHOT"));
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("serde error")]
+    Serde(#[from] serde_json::Error),
+    #[error("snapshot has schema {found}, want {SNAPSHOT_SCHEMA_VERSION}")]
+    SchemaMismatch { found: u32 },
+    #[error("{0} isn't cached yet")]
+    MissingCache(&'static str),
+    #[error("cache write error")]
+    Cache(#[from] CacheError<std::convert::Infallible>),
+}
+
+/// Everything the engine needs from warframe.market to start, in one file.
+/// Seeding an empty `CACHE_PATH` with it lets a new machine start without
+/// network; the data is refreshed later like any other stale cache.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub schema_version: u32,
+    /// oldest fetch the snapshot was built from
+    pub created_at: DateTime<Utc>,
+    pub api_version: Option<String>,
+    pub item_identifiers: Vec<ItemIdentifier>,
+    pub items: HashMap<String, Item>,
+    pub sets: HashMap<String, ItemSet>,
+    pub relics: Vec<Relic>,
+}
+
+impl Snapshot {
+    /// build a snapshot from a warm cache
+    pub async fn from_cache(cache_path: &Path) -> Result<Self, SnapshotError> {
+        let item_identifiers =
+            cache_read::<Vec<ItemIdentifier>>(&cache_path.join(ITEM_IDENTIFIERS_FILE))
+                .await
+                .ok_or(SnapshotError::MissingCache(ITEM_IDENTIFIERS_FILE))?;
+        let items_and_sets = cache_read::<(HashMap<String, Item>, HashMap<String, ItemSet>)>(
+            &cache_path.join(ITEMS_AND_SETS_FILE),
+        )
+        .await
+        .ok_or(SnapshotError::MissingCache(ITEMS_AND_SETS_FILE))?;
+        let relics = cache_read::<Vec<Relic>>(&cache_path.join(RELICS_FILE))
+            .await
+            .ok_or(SnapshotError::MissingCache(RELICS_FILE))?;
+        let created_at = item_identifiers
+            .fetched_at
+            .min(items_and_sets.fetched_at)
+            .min(relics.fetched_at);
+        let (items, sets) = items_and_sets.data;
+        Ok(Self {
+            schema_version: SNAPSHOT_SCHEMA_VERSION,
+            created_at,
+            api_version: items_and_sets.api_version,
+            item_identifiers: item_identifiers.data,
+            items,
+            sets,
+            relics: relics.data,
+        })
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        #[derive(Deserialize)]
+        struct Header {
+            schema_version: u32,
+        }
+        let Header { schema_version } = serde_json::from_slice(bytes)?;
+        if schema_version != SNAPSHOT_SCHEMA_VERSION {
+            return Err(SnapshotError::SchemaMismatch {
+                found: schema_version,
+            });
+        }
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    pub async fn read(path: &Path) -> Result<Self, SnapshotError> {
+        Self::from_slice(&fs::read(path).await?)
+    }
+
+    /// replace `path` with the snapshot, atomically like the cache files
+    pub async fn write(&self, path: &Path) -> Result<(), SnapshotError> {
+        write_atomic(path, &serde_json::to_vec(self)?).await?;
+        Ok(())
+    }
+
+    /// the snapshot at `path` if given, otherwise the bundled one if this
+    /// build has it
+    pub async fn load(path: Option<&Path>) -> Result<Option<Self>, SnapshotError> {
+        match path {
+            Some(path) => Self::read(path).await.map(Some),
+            #[cfg(feature = "bundled-snapshot")]
+            None => Self::from_slice(BUNDLED).map(Some),
+            #[cfg(not(feature = "bundled-snapshot"))]
+            None => Ok(None),
+        }
+    }
+
+    /// Write every cache file under `cache_path` that's missing or unusable
+    /// from the snapshot, stamped with its creation time so the usual
+    /// staleness check picks them up for a refresh. Files already cached are
+    /// left alone.
+    pub async fn seed_cache(&self, cache_path: &Path) -> Result<(), SnapshotError> {
+        fs::create_dir_all(cache_path).await?;
+        self.seed(
+            &cache_path.join(ITEM_IDENTIFIERS_FILE),
+            &self.item_identifiers,
+        )
+        .await?;
+        self.seed(
+            &cache_path.join(ITEMS_AND_SETS_FILE),
+            &(&self.items, &self.sets),
+        )
+        .await?;
+        self.seed(&cache_path.join(RELICS_FILE), &self.relics).await
+    }
+
+    async fn seed<T: Serialize>(&self, path: &Path, data: &T) -> Result<(), SnapshotError> {
+        let _lock = CacheLock::acquire(path, true).await?;
+        if read_envelope::<serde_json::Value>(path).await.is_some() {
+            return Ok(());
+        }
+        event!(Level::INFO, "seeding {path:?} from snapshot");
+        let envelope = CacheEnvelope {
+            schema_version: CACHE_SCHEMA_VERSION,
+            fetched_at: self.created_at,
+            api_version: self.api_version.clone(),
+            data,
+        };
+        write_envelope(path, &envelope).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cephalon_rust_mock_market::MOCK_REQUESTS_PER_SECOND;
+
+    use crate::{
+        config::MarketClient,
+        items::{cached_fetch_relics, cached_get_item_identifiers, cached_items_and_sets},
+        test_support::mock_market,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn seeds_a_cold_cache() {
+        let warm = tempfile::tempdir().unwrap();
+        let market = mock_market().await;
+        let identifiers = cached_get_item_identifiers(warm.path(), &market, &|_| {})
+            .await
+            .unwrap()
+            .data;
+        cached_fetch_relics(warm.path(), &market, &identifiers, &|_| {})
+            .await
+            .unwrap();
+        let (items, _) = cached_items_and_sets(warm.path(), &market, &identifiers, &|_| {})
+            .await
+            .unwrap()
+            .data;
+
+        let file = warm.path().join("snapshot.json");
+        Snapshot::from_cache(warm.path())
+            .await
+            .unwrap()
+            .write(&file)
+            .await
+            .unwrap();
+        // the temp file it went through is gone
+        let leftovers = std::fs::read_dir(warm.path())
+            .unwrap()
+            .map(|x| x.unwrap().file_name())
+            .filter(|x| x.to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        let snapshot = Snapshot::load(Some(&file)).await.unwrap().unwrap();
+
+        let cold = tempfile::tempdir().unwrap();
+        snapshot.seed_cache(cold.path()).await.unwrap();
+        // nothing listens there, so anything but a cache hit fails
+        let offline = MarketClient::new("http://127.0.0.1:9", MOCK_REQUESTS_PER_SECOND);
+        let seeded = cached_items_and_sets(cold.path(), &offline, &[], &|_| {})
+            .await
+            .unwrap();
+        assert_eq!(seeded.data.0, items);
+        assert_eq!(seeded.fetched_at, snapshot.created_at);
+    }
+
+    #[tokio::test]
+    async fn missing_cache_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            Snapshot::from_cache(dir.path()).await,
+            Err(SnapshotError::MissingCache(ITEM_IDENTIFIERS_FILE))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_schemas() {
+        assert!(matches!(
+            Snapshot::from_slice(br#"{"schema_version": 0}"#),
+            Err(SnapshotError::SchemaMismatch { found: 0 })
+        ));
+    }
+}
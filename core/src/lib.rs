@@ -1,27 +1,55 @@
 #![allow(clippy::single_match)]
 
-use event::Event;
+use chrono::{DateTime, Utc};
+use config::MarketClient;
+use event::{CloseReason, EquippedRelic, Event, ItemDatabasePhase};
+use game_state::{GameChange, GameState, LoggedRelic};
 use geometry::WindowRect;
 use image::DynamicImage;
 use items::{
-    cached_get_item_identifiers, cached_items_and_sets, items::Item, CacheError, ReqwestSerdeError,
+    cached_drop_tables, cached_fetch_relics, cached_get_item_identifiers, cached_items_and_sets,
+    drop_tables::DropTables,
+    items::ItemDatabase,
+    price_cache::PriceCache,
+    refresh_items_and_sets, refresh_relics,
+    relic_value::drop_prices,
+    snapshot::{Snapshot, SnapshotError},
+    CacheError, FetchProgress, ReqwestSerdeError, ITEM_CACHE_MAX_AGE,
+};
+use log_watcher::{LiveLog, LogEntry, LogEvent, LogSource};
+use reward_session::{
+    run_reward_session, screen_shut_down, CaptureSource, SessionCancel, REWARD_PICK_WINDOW,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
-use log_watcher::{watcher, LogEntry};
-use reward_session::{run_reward_session, CaptureSource, REWARD_PICK_WINDOW};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
 use thiserror::Error;
-use tokio::{fs::create_dir_all, sync::mpsc::Sender};
+use tokio::{
+    fs::create_dir_all,
+    sync::{mpsc::Sender, oneshot, watch},
+    task::{Id, JoinSet},
+};
 use tracing::*;
+use valuation::Valuation;
 use xcap::Window;
 
 pub mod config;
+pub mod game_state;
 pub mod geometry;
 pub mod items;
 pub mod log_watcher;
 pub mod event;
 pub mod ocr;
+pub mod recommendation;
 pub mod relic_screen_parser;
 pub mod reward_session;
+pub mod settings;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod valuation;
 
 pub struct WindowCapture(pub Window);
 
@@ -65,102 +93,479 @@ fn primary_monitor_capture() -> Option<MonitorCapture> {
         .map(MonitorCapture)
 }
 
+/// `logged` resolved against `db`, the local player's first. Relics `db`
+/// doesn't know are left out and logged.
+fn equipped_relics(logged: &[LoggedRelic], db: &ItemDatabase) -> Vec<EquippedRelic> {
+    let mut relics = logged
+        .iter()
+        .flat_map(|x| {
+            let relic = x.resolve(db);
+            if relic.is_none() {
+                event!(Level::WARN, "unknown relic {:?} in the log", x.name);
+            }
+            relic
+        })
+        .collect::<Vec<_>>();
+    relics.sort_by_key(|x| x.player.is_some());
+    relics
+}
+
+/// What the game's reward screen is captured from, and where its window is.
+fn live_capture() -> Option<(Arc<dyn CaptureSource>, Option<WindowRect>)> {
+    match find_warframe_window() {
+        Some(window) => {
+            // window rect in screen coords so frontends can position UI
+            // relative to the game window (half-ultrawide, second monitor)
+            let rect = window_rect(&window);
+            Some((Arc::new(WindowCapture(window)), rect))
+        }
+        None => {
+            // no X11 window: warframe may be running as a native wayland
+            // client (PROTON_ENABLE_WAYLAND) where xcap can't enumerate
+            // windows. fall back to capturing the primary monitor —
+            // borderless game means the frame still contains the cards,
+            // and window=None tells frontends to assume monitor-sized.
+            let capture = primary_monitor_capture()?;
+            event!(
+                Level::INFO,
+                "no warframe window found, falling back to primary monitor capture"
+            );
+            Some((Arc::new(capture), None))
+        }
+    }
+}
+
+/// A fixed image as the screen, for replays and tests.
+pub struct ImageCapture(pub DynamicImage);
+
+impl ImageCapture {
+    /// a screenshot of the reward screen
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self(image::open(path)?))
+    }
+}
+
+impl CaptureSource for ImageCapture {
+    fn capture(&self) -> anyhow::Result<DynamicImage> {
+        Ok(self.0.clone())
+    }
+}
+
 pub struct Engine {
-    items: Arc<HashMap<String, Item>>,
+    /// swapped out wholesale when a background refresh finishes; sessions
+    /// already running keep the snapshot they started with
+    db: watch::Receiver<Arc<ItemDatabase>>,
+    prices: Arc<PriceCache>,
+    valuation: Arc<Valuation>,
+    /// stands in for the game's window when set
+    capture: Option<Arc<dyn CaptureSource>>,
+    /// what the log said about the game so far, kept up to date by `run`
+    game: watch::Sender<GameState>,
 }
 
 #[derive(Error, Debug)]
 pub enum EngineCreateError {
     #[error("create cache path error")]
     CreateCachePathError(#[from] std::io::Error),
-    #[error("create cache path error")]
+    #[error("fetch item data error")]
     FetchError(#[from] CacheError<ReqwestSerdeError>),
+    #[error("seed cache from snapshot error")]
+    SnapshotError(#[from] SnapshotError),
+}
+
+/// how long a failed background refresh of stale item data waits before
+/// trying again, e.g. when started offline from a snapshot
+const ITEM_REFRESH_RETRY: Duration = Duration::from_secs(5 * 60);
+
+/// refetch items and sets (and relics if `relics_stale`) into a new database,
+/// with the drop tables at `drop_data` resolved against it again
+async fn refresh_database(
+    cache_path: &Path,
+    market: &MarketClient,
+    current: &ItemDatabase,
+    relics_stale: bool,
+    drop_data: Option<&Path>,
+) -> Result<ItemDatabase, CacheError<ReqwestSerdeError>> {
+    let (items, sets) = refresh_items_and_sets(cache_path, market).await?;
+    let relics = match relics_stale {
+        true => {
+            let item_identifiers = cached_get_item_identifiers(cache_path, market, &|_| {})
+                .await?
+                .data;
+            refresh_relics(cache_path, market, &item_identifiers)
+                .await?
+                .into_iter()
+                .map(|x| (x.id.clone(), x))
+                .collect()
+        }
+        false => current.relics.clone(),
+    };
+    let drop_tables = match drop_data {
+        Some(source) => cached_drop_tables(cache_path, source, &items, &relics)
+            .await
+            .unwrap_or_else(|e| {
+                event!(Level::WARN, "keeping the old drop tables, {source:?}: {e}");
+                current.drop_tables.clone()
+            }),
+        None => DropTables::default(),
+    };
+    Ok(ItemDatabase {
+        items,
+        sets,
+        relics,
+        drop_tables,
+    })
+}
+
+/// Refresh the database in `db` whenever its cached data goes stale, for as
+/// long as anyone's watching it. `items_fetched_at` and `relics_fetched_at`
+/// are how old what it started with is; stale already means right away.
+async fn keep_refreshed(
+    db: watch::Sender<Arc<ItemDatabase>>,
+    cache_path: PathBuf,
+    market: MarketClient,
+    drop_data: Option<PathBuf>,
+    items_fetched_at: DateTime<Utc>,
+    relics_fetched_at: DateTime<Utc>,
+) {
+    let max_age = chrono::Duration::from_std(ITEM_CACHE_MAX_AGE).expect("max age fits");
+    let mut items_stale_at = items_fetched_at + max_age;
+    let mut relics_stale_at = relics_fetched_at + max_age;
+    let mut next = items_stale_at.min(relics_stale_at);
+    loop {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            // the engine is gone, nobody to refresh for
+            _ = db.closed() => return,
+        }
+        event!(
+            Level::INFO,
+            "item cache from {} is stale, refreshing in the background",
+            items_stale_at.min(relics_stale_at) - max_age
+        );
+        let relics_stale = relics_stale_at <= Utc::now();
+        let current = db.borrow().clone();
+        let refreshed = refresh_database(
+            &cache_path,
+            &market,
+            &current,
+            relics_stale,
+            drop_data.as_deref(),
+        );
+        match refreshed.await {
+            Ok(refreshed) => {
+                event!(
+                    Level::INFO,
+                    "item cache refreshed, {} items",
+                    refreshed.items.len()
+                );
+                db.send_replace(Arc::new(refreshed));
+                items_stale_at = Utc::now() + max_age;
+                if relics_stale {
+                    relics_stale_at = items_stale_at;
+                }
+                next = items_stale_at.min(relics_stale_at);
+            }
+            Err(e) => {
+                event!(
+                    Level::WARN,
+                    "item cache refresh failed, keeping stale data and retrying in {ITEM_REFRESH_RETRY:?}: {e}"
+                );
+                next = Utc::now() + ITEM_REFRESH_RETRY;
+            }
+        }
+    }
 }
 
 impl Engine {
-    pub async fn new(cache_path: PathBuf) -> Result<Self, EngineCreateError> {
+    pub async fn new(
+        cache_path: PathBuf,
+        market: &MarketClient,
+        prices: Arc<PriceCache>,
+        valuation: Valuation,
+        snapshot: Option<&Snapshot>,
+        drop_data: Option<&Path>,
+        sender: &Sender<Event>,
+    ) -> Result<Self, EngineCreateError> {
+        let engine = Self::load(
+            cache_path, market, prices, valuation, snapshot, drop_data, sender,
+        )
+        .await;
+        if let Err(e) = &engine {
+            let error = e.to_string();
+            let _ = sender.send(Event::ItemDatabaseFailed { error }).await;
+        }
+        engine
+    }
+
+    /// `new`, minus telling `sender` when it fails
+    async fn load(
+        cache_path: PathBuf,
+        market: &MarketClient,
+        prices: Arc<PriceCache>,
+        valuation: Valuation,
+        snapshot: Option<&Snapshot>,
+        drop_data: Option<&Path>,
+        sender: &Sender<Event>,
+    ) -> Result<Self, EngineCreateError> {
         create_dir_all(&cache_path).await?;
-        let item_identifiers = cached_get_item_identifiers(&cache_path).await?;
-        let (items, _sets) = cached_items_and_sets(&cache_path, &item_identifiers).await?;
+        if let Some(snapshot) = snapshot {
+            snapshot.seed_cache(&cache_path).await?;
+        }
+        // progress is best-effort: a full channel drops the update, the next
+        // one supersedes it anyway
+        let report = |phase| {
+            move |progress: FetchProgress| {
+                let _ = sender.try_send(Event::ItemDatabaseProgress {
+                    phase,
+                    progress,
+                    retries: market.retries(),
+                });
+            }
+        };
+        let item_identifiers =
+            cached_get_item_identifiers(&cache_path, market, &report(ItemDatabasePhase::ItemList))
+                .await?;
+        let items_and_sets = cached_items_and_sets(
+            &cache_path,
+            market,
+            &item_identifiers.data,
+            &report(ItemDatabasePhase::ItemSets),
+        )
+        .await?;
+        let relics = cached_fetch_relics(
+            &cache_path,
+            market,
+            &item_identifiers.data,
+            &report(ItemDatabasePhase::Relics),
+        )
+        .await?;
+        let (items, sets) = items_and_sets.data;
+        let items_fetched_at = item_identifiers.fetched_at.min(items_and_sets.fetched_at);
+        let relics_fetched_at = relics.fetched_at;
+        let relics = relics
+            .data
+            .into_iter()
+            .map(|x| (x.id.clone(), x))
+            .collect::<HashMap<_, _>>();
+        // drop tables are optional extras, a broken file shouldn't keep the
+        // engine from starting
+        let drop_tables = match drop_data {
+            Some(source) => cached_drop_tables(&cache_path, source, &items, &relics)
+                .await
+                .unwrap_or_else(|e| {
+                    event!(Level::WARN, "ignoring drop data {source:?}: {e}");
+                    DropTables::default()
+                }),
+            None => DropTables::default(),
+        };
+        let _ = sender.send(Event::ItemDatabaseReady).await;
+        let (db_tx, db) = watch::channel(Arc::new(ItemDatabase {
+            items,
+            sets,
+            relics,
+            drop_tables,
+        }));
+        tokio::spawn(keep_refreshed(
+            db_tx,
+            cache_path,
+            market.clone(),
+            drop_data.map(Path::to_path_buf),
+            items_fetched_at,
+            relics_fetched_at,
+        ));
         Ok(Self {
-            items: Arc::new(items),
+            db,
+            prices,
+            valuation: Arc::new(valuation),
+            capture: None,
+            game: watch::Sender::new(GameState::default()),
         })
     }
 
-    pub async fn run(self, sender: Sender<Event>) {
-        let mut squad_size = 4;
-        let mut receiver = watcher().await;
+    /// the item database as of now; a background refresh may replace it later
+    pub fn database(&self) -> Arc<ItemDatabase> {
+        self.db.borrow().clone()
+    }
+
+    pub fn prices(&self) -> &PriceCache {
+        &self.prices
+    }
 
-        while let Some(entry) = receiver.recv().await {
-            match entry {
+    /// warm the price cache with what `relic` drops, so the reward screen
+    /// it's cracked for shows up priced sooner. needs its drop table
+    fn prefetch_drops(&self, relic: &LoggedRelic) {
+        let db = self.db.borrow().clone();
+        let Some(relic) = relic.resolve(&db) else {
+            return;
+        };
+        if db.drop_tables.for_relic(&relic.relic.id).is_none() {
+            return;
+        }
+        let prices = self.prices.clone();
+        tokio::spawn(async move {
+            let table = db.drop_tables.for_relic(&relic.relic.id);
+            drop_prices(table, &db, &prices).await;
+        });
+    }
+
+    /// the game as far as EE.log tells, updated as `run` reads it
+    pub fn game_state(&self) -> watch::Receiver<GameState> {
+        self.game.subscribe()
+    }
+
+    /// Capture reward screens from `capture` instead of the game's window,
+    /// e.g. a screenshot for a [`Replay`](log_watcher::replay::Replay).
+    pub fn with_capture(mut self, capture: impl CaptureSource) -> Self {
+        self.capture = Some(Arc::new(capture));
+        self
+    }
+
+    /// watch EE.log at `log_path`, or wherever it's found if `None`
+    pub async fn run(self, sender: Sender<Event>, log_path: Option<PathBuf>) {
+        self.run_from(sender, LiveLog { path: log_path }).await
+    }
+
+    /// Follow the log `source` reads. Returns once it runs out (the live log
+    /// never does) and the reward sessions it started are over.
+    pub async fn run_from(self, sender: Sender<Event>, source: impl LogSource) {
+        // reach the reward session running right now, if any
+        let mut shut_down_sender: Option<oneshot::Sender<()>> = None;
+        let mut session_cancel: Option<(Id, SessionCancel)> = None;
+        let mut sessions = JoinSet::new();
+        let mut receiver = source.start();
+
+        while let Some(log_event) = receiver.recv().await {
+            // finished sessions are only reaped here, their results aren't needed.
+            // once the current one is among them there's nothing left to reach
+            while let Some(joined) = sessions.try_join_next_with_id() {
+                let id = joined.map_or_else(|e| e.id(), |(id, ())| id);
+                if session_cancel.as_ref().is_some_and(|(x, _)| *x == id) {
+                    session_cancel = None;
+                    shut_down_sender = None;
+                }
+            }
+            let line = match log_event {
+                LogEvent::Entry(line) => line,
+                LogEvent::Backlog(line) => {
+                    // only for what it says about the game, nothing it
+                    // announced is still going on
+                    self.game
+                        .send_if_modified(|state| !state.apply(&line.entry).is_empty());
+                    continue;
+                }
+                LogEvent::Restarted => {
+                    // the game state and the session were about the old game
+                    let mut changes = Vec::new();
+                    self.game.send_modify(|state| changes = state.reset());
+                    shut_down_sender = None;
+                    if let Some((_, cancel)) = session_cancel.take() {
+                        cancel.cancel(CloseReason::GameRestarted);
+                    }
+                    let _ = sender.send(Event::GameRestarted).await;
+                    for change in changes {
+                        let _ = sender.send(Event::GameStateChanged(change)).await;
+                    }
+                    continue;
+                }
+            };
+            let mut changes = Vec::new();
+            self.game.send_if_modified(|state| {
+                changes = state.apply(&line.entry);
+                !changes.is_empty()
+            });
+            for change in changes {
+                event!(Level::INFO, "{change}");
+                let ends_session = match &change {
+                    GameChange::Relic(relic) => {
+                        self.prefetch_drops(relic);
+                        None
+                    }
+                    GameChange::MissionAborted => Some(CloseReason::Aborted),
+                    GameChange::HostMigration => Some(CloseReason::HostMigration),
+                    GameChange::Disconnected => Some(CloseReason::Disconnected),
+                    _ => None,
+                };
+                // what the session shows doesn't survive any of these
+                if let Some(reason) = ends_session {
+                    if let Some((_, cancel)) = session_cancel.take() {
+                        shut_down_sender = None;
+                        cancel.cancel(reason);
+                    }
+                }
+                let _ = sender.send(Event::GameStateChanged(change)).await;
+            }
+            if screen_shut_down(&line.entry) {
+                if let Some(shut_down_sender) = shut_down_sender.take() {
+                    event!(Level::INFO, "reward screen shut down");
+                    let _ = shut_down_sender.send(());
+                }
+                continue;
+            }
+            match line.entry {
                 LogEntry::ScriptInfo { script, content }
                     if script == "ProjectionRewardChoice"
                         && content == "Relic rewards initialized" =>
                 {
-                    event!(Level::INFO, "relic reward screen detected");
-                    match find_warframe_window() {
-                        Some(window) => {
-                            // window rect in screen coords so frontends can position UI
-                            // relative to the game window (half-ultrawide, second monitor)
-                            let rect = window_rect(&window);
-                            let capture = WindowCapture(window);
-                            let items = self.items.clone();
+                    let latency = line.at.map(|at| Utc::now() - at).unwrap_or_default();
+                    event!(
+                        Level::INFO,
+                        "relic reward screen detected, logged {}ms ago",
+                        latency.num_milliseconds()
+                    );
+                    // the game only shows one; if the last one's still up
+                    // here, whatever closed it never made it to the log
+                    shut_down_sender = None;
+                    if let Some((_, cancel)) = session_cancel.take() {
+                        cancel.cancel(CloseReason::Replaced);
+                    }
+                    let (sender_for_shut_down, shut_down) = oneshot::channel();
+                    let cancel = SessionCancel::default();
+                    let capture = match &self.capture {
+                        Some(capture) => Some((capture.clone(), None)),
+                        None => live_capture(),
+                    };
+                    match capture {
+                        Some((capture, rect)) => {
+                            let db = self.db.borrow().clone();
+                            let prices = self.prices.clone();
+                            let valuation = self.valuation.clone();
                             let sender = sender.clone();
-                            let count = squad_size;
-                            tokio::spawn(async move {
+                            let (count, relics) = {
+                                let state = self.game.borrow();
+                                (state.squad_size, equipped_relics(&state.relics, &db))
+                            };
+                            let cancel_for_session = cancel.clone();
+                            let session = sessions.spawn(async move {
                                 run_reward_session(
-                                    &capture,
-                                    &items,
+                                    &*capture,
+                                    &db,
+                                    &prices,
+                                    &valuation,
                                     &sender,
                                     count,
                                     rect,
+                                    relics,
                                     REWARD_PICK_WINDOW,
+                                    shut_down,
+                                    cancel_for_session,
                                 )
                                 .await;
                             });
+                            shut_down_sender = Some(sender_for_shut_down);
+                            session_cancel = Some((session.id(), cancel));
                         }
-                        None => {
-                            // no X11 window: warframe may be running as a native wayland
-                            // client (PROTON_ENABLE_WAYLAND) where xcap can't enumerate
-                            // windows. fall back to capturing the primary monitor —
-                            // borderless game means the frame still contains the cards,
-                            // and window=None tells frontends to assume monitor-sized.
-                            match primary_monitor_capture() {
-                                Some(capture) => {
-                                    event!(
-                                        Level::INFO,
-                                        "no warframe window found, falling back to primary monitor capture"
-                                    );
-                                    let items = self.items.clone();
-                                    let sender = sender.clone();
-                                    let count = squad_size;
-                                    tokio::spawn(async move {
-                                        run_reward_session(
-                                            &capture,
-                                            &items,
-                                            &sender,
-                                            count,
-                                            None,
-                                            REWARD_PICK_WINDOW,
-                                        )
-                                        .await;
-                                    });
-                                }
-                                None => event!(
-                                    Level::WARN,
-                                    "reward screen detected but no warframe window and no monitor to capture"
-                                ),
-                            }
-                        }
+                        None => event!(
+                            Level::WARN,
+                            "reward screen detected but no warframe window and no monitor to capture"
+                        ),
                     }
                 }
-                LogEntry::NetInfo(x) if x == "Num session players: 1" => squad_size = 1,
-                LogEntry::NetInfo(x) if x == "Num session players: 2" => squad_size = 2,
-                LogEntry::NetInfo(x) if x == "Num session players: 3" => squad_size = 3,
-                LogEntry::NetInfo(x) if x == "Num session players: 4" => squad_size = 4,
                 _ => {}
             }
         }
+        while sessions.join_next().await.is_some() {}
     }
 }
 
@@ -0,0 +1,202 @@
+use std::path::{Path, PathBuf};
+
+use ctreg::regex;
+
+/// warframe's steam app id, names its proton prefix
+const APP_ID: &str = "230410";
+
+/// EE.log relative to a windows user profile
+const LOG_IN_PROFILE: [&str; 4] = ["AppData", "Local", "Warframe", "EE.log"];
+
+regex! { LibraryFolderPath = r#"^\s*"path"\s+"(?<path>.*)"\s*$"# }
+
+/// steam library roots listed in a `libraryfolders.vdf`
+fn library_folders(vdf: &str) -> Vec<PathBuf> {
+    let parser = LibraryFolderPath::new();
+    vdf.lines()
+        .flat_map(|line| parser.captures(line))
+        .map(|x| PathBuf::from(x.path.content.replace("\\\\", "\\")))
+        .collect()
+}
+
+fn in_profile(profile: PathBuf) -> PathBuf {
+    profile.join(LOG_IN_PROFILE.iter().collect::<PathBuf>())
+}
+
+/// EE.log in every user profile of a wine prefix. `user` stands in when the
+/// profiles can't be listed, so the diagnostic still shows where it looked.
+fn wine_prefix_logs(prefix: &Path, user: Option<&str>) -> Vec<PathBuf> {
+    let users = prefix.join("drive_c").join("users");
+    let profiles = std::fs::read_dir(&users)
+        .map(|dir| {
+            dir.flatten()
+                .map(|x| x.path())
+                .filter(|x| x.is_dir() && !x.ends_with("Public"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    match (profiles.is_empty(), user) {
+        (false, _) => profiles.into_iter().map(in_profile).collect(),
+        (true, Some(user)) => vec![in_profile(users.join(user))],
+        (true, None) => Vec::new(),
+    }
+}
+
+/// every directory right under `dir`, nothing if it can't be read
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = std::fs::read_dir(dir)
+        .map(|dir| {
+            dir.flatten()
+                .map(|x| x.path())
+                .filter(|x| x.is_dir())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    dirs.sort();
+    dirs
+}
+
+/// Where EE.log can be under `home` on linux, most common install first:
+/// native and flatpak steam (and their extra libraries), lutris and heroic
+/// prefixes, and the standalone launcher in the default wine prefix.
+fn unix_candidates(home: &Path, user: Option<&str>) -> Vec<PathBuf> {
+    let steam_roots = [
+        home.join(".local/share/Steam"),
+        home.join(".steam/steam"),
+        home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"),
+        home.join(".var/app/com.valvesoftware.Steam/data/Steam"),
+    ];
+    let libraries = steam_roots.iter().flat_map(|root| {
+        let vdf =
+            std::fs::read_to_string(root.join("steamapps/libraryfolders.vdf")).unwrap_or_default();
+        std::iter::once(root.clone()).chain(library_folders(&vdf))
+    });
+    // proton always names the profile steamuser
+    let steam = libraries.map(|library| {
+        in_profile(
+            library
+                .join("steamapps/compatdata")
+                .join(APP_ID)
+                .join("pfx/drive_c/users/steamuser"),
+        )
+    });
+    let lutris = subdirs(&home.join("Games"));
+    let heroic = [
+        home.join("Games/Heroic/Prefixes"),
+        home.join("Games/Heroic/Prefixes/default"),
+    ]
+    .iter()
+    .flat_map(|x| subdirs(x))
+    .collect::<Vec<_>>();
+    let prefixes = lutris
+        .iter()
+        .chain(&heroic)
+        .chain(std::iter::once(&home.join(".wine")))
+        .flat_map(|prefix| match prefix.join("drive_c").is_dir() {
+            true => wine_prefix_logs(prefix, user),
+            false => Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    let mut candidates = steam.collect::<Vec<_>>();
+    candidates.extend(prefixes);
+    // the standalone launcher in the default prefix, listed even when it
+    // doesn't exist so the diagnostic mentions it
+    if let Some(user) = user {
+        candidates.push(in_profile(home.join(".wine/drive_c/users").join(user)));
+    }
+    let mut seen = std::collections::HashSet::new();
+    candidates.retain(|x| seen.insert(x.clone()));
+    candidates
+}
+
+/// Every place EE.log is looked for, most likely first. Empty if there's no
+/// home directory to look in.
+pub fn candidate_paths() -> Vec<PathBuf> {
+    #[cfg(target_os = "windows")]
+    {
+        // steam and the standalone launcher both log into the user's profile
+        dirs::data_local_dir()
+            .map(|x| x.join("Warframe").join("EE.log"))
+            .into_iter()
+            .collect()
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let user = std::env::var("USER").ok();
+        dirs::home_dir()
+            .map(|home| unix_candidates(&home, user.as_deref()))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_library_folders() {
+        let vdf = r#"
+"libraryfolders"
+{
+	"0"
+	{
+		"path"		"/home/tenno/.local/share/Steam"
+		"label"		""
+	}
+	"1"
+	{
+		"path"		"/mnt/games/SteamLibrary"
+		"apps"
+		{
+			"230410"		"51234567890"
+		}
+	}
+	"2"
+	{
+		"path"		"D:\\SteamLibrary"
+	}
+}
+"#;
+        assert_eq!(
+            library_folders(vdf),
+            vec![
+                PathBuf::from("/home/tenno/.local/share/Steam"),
+                PathBuf::from("/mnt/games/SteamLibrary"),
+                PathBuf::from("D:\\SteamLibrary"),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_prefixes_under_home() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path();
+        let library = home.join("SteamLibrary");
+        let flatpak = home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam");
+        std::fs::create_dir_all(flatpak.join("steamapps")).unwrap();
+        std::fs::write(
+            flatpak.join("steamapps/libraryfolders.vdf"),
+            format!("\"path\"\t\t\"{}\"\n", library.display()),
+        )
+        .unwrap();
+        let lutris = home.join("Games/warframe/drive_c/users/tenno");
+        std::fs::create_dir_all(&lutris).unwrap();
+        std::fs::create_dir_all(home.join("Games/warframe/drive_c/users/Public")).unwrap();
+
+        let candidates = unix_candidates(home, Some("tenno"));
+        let proton = |root: &Path| {
+            in_profile(root.join("steamapps/compatdata/230410/pfx/drive_c/users/steamuser"))
+        };
+        assert_eq!(candidates[0], proton(&home.join(".local/share/Steam")));
+        assert!(candidates.contains(&proton(&flatpak)));
+        assert!(candidates.contains(&proton(&library)));
+        assert!(candidates.contains(&in_profile(lutris)));
+        assert!(!candidates
+            .iter()
+            .any(|x| x.starts_with(home.join("Games/warframe/drive_c/users/Public"))));
+        assert_eq!(
+            candidates.last(),
+            Some(&in_profile(home.join(".wine/drive_c/users/tenno")))
+        );
+    }
+}
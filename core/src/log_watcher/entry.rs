@@ -0,0 +1,304 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use ctreg::regex;
+
+regex! { LogEntryParser = r#"^(?:(?<time>\d+\.\d+) )?(?<system>[A-Za-z]+) \[(?<level>[A-Za-z]+)\]: (?<rest>.*)$"# }
+regex! { LogScriptEntryParser = r#"^(?<script>[A-Za-z]+)\.lua: (?<rest>.*)$"# }
+
+/// What part of the game logged an entry, the `Sys` in `Sys [Info]: ...`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum System {
+    Sys,
+    Net,
+    Phys,
+    Snd,
+    Gfx,
+    Input,
+    AI,
+    Game,
+    Anim,
+    Script,
+    /// one this crate doesn't know (yet)
+    Other(String),
+}
+
+impl From<&str> for System {
+    fn from(s: &str) -> Self {
+        match s {
+            "Sys" => Self::Sys,
+            "Net" => Self::Net,
+            "Phys" => Self::Phys,
+            "Snd" => Self::Snd,
+            "Gfx" => Self::Gfx,
+            "Input" => Self::Input,
+            "AI" => Self::AI,
+            "Game" => Self::Game,
+            "Anim" => Self::Anim,
+            "Script" => Self::Script,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for System {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Other(x) => f.write_str(x),
+            known => write!(f, "{known:?}"),
+        }
+    }
+}
+
+/// The `Info` in `Sys [Info]: ...`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+    /// one this crate doesn't know (yet)
+    Other(String),
+}
+
+impl From<&str> for Level {
+    fn from(s: &str) -> Self {
+        match s {
+            "Info" => Self::Info,
+            "Warning" => Self::Warning,
+            "Error" => Self::Error,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Other(x) => f.write_str(x),
+            known => write!(f, "{known:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogEntry {
+    SysInfo(String),
+    SysWarning(String),
+    SysError(String),
+    NetInfo(String),
+    NetError(String),
+    PhysInfo(String),
+    PhysWarning(String),
+    PhysError(String),
+    SndInfo(String),
+    GfxInfo(String),
+    InputInfo(String),
+    AIInfo(String),
+    GameInfo(String),
+    GameWarning(String),
+    AnimInfo(String),
+    ScriptInfo {
+        script: String,
+        content: String,
+    },
+    /// any entry without a variant of its own
+    Other {
+        system: System,
+        level: Level,
+        message: String,
+    },
+}
+
+impl LogEntry {
+    /// the entry for a `system [level]: message` line
+    pub fn new(system: System, level: Level, message: &str) -> Self {
+        let text = message.to_string();
+        match (&system, &level) {
+            (System::Sys, Level::Info) => Self::SysInfo(text),
+            (System::Sys, Level::Warning) => Self::SysWarning(text),
+            (System::Sys, Level::Error) => Self::SysError(text),
+            (System::Net, Level::Info) => Self::NetInfo(text),
+            (System::Net, Level::Error) => Self::NetError(text),
+            (System::Phys, Level::Info) => Self::PhysInfo(text),
+            (System::Phys, Level::Warning) => Self::PhysWarning(text),
+            (System::Phys, Level::Error) => Self::PhysError(text),
+            (System::Snd, Level::Info) => Self::SndInfo(text),
+            (System::Gfx, Level::Info) => Self::GfxInfo(text),
+            (System::Input, Level::Info) => Self::InputInfo(text),
+            (System::AI, Level::Info) => Self::AIInfo(text),
+            (System::Game, Level::Info) => Self::GameInfo(text),
+            (System::Game, Level::Warning) => Self::GameWarning(text),
+            (System::Anim, Level::Info) => Self::AnimInfo(text),
+            (System::Script, Level::Info) => match LogScriptEntryParser::new().captures(message) {
+                Some(captures) => Self::ScriptInfo {
+                    script: captures.script.content.trim().to_string(),
+                    content: captures.rest.content.trim().to_string(),
+                },
+                None => Self::Other {
+                    system,
+                    level,
+                    message: text,
+                },
+            },
+            _ => Self::Other {
+                system,
+                level,
+                message: text,
+            },
+        }
+    }
+
+    fn text_mut(&mut self) -> &mut String {
+        match self {
+            Self::SysInfo(x)
+            | Self::SysWarning(x)
+            | Self::SysError(x)
+            | Self::NetInfo(x)
+            | Self::NetError(x)
+            | Self::PhysInfo(x)
+            | Self::PhysWarning(x)
+            | Self::PhysError(x)
+            | Self::SndInfo(x)
+            | Self::GfxInfo(x)
+            | Self::InputInfo(x)
+            | Self::AIInfo(x)
+            | Self::GameInfo(x)
+            | Self::GameWarning(x)
+            | Self::AnimInfo(x) => x,
+            Self::ScriptInfo { content, .. } => content,
+            Self::Other { message, .. } => message,
+        }
+    }
+
+    /// Add a continuation line to the entry's text, on a line of its own and
+    /// with its indentation kept. Blank lines add nothing.
+    pub fn continue_with(&mut self, line: &str) {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            return;
+        }
+        let text = self.text_mut();
+        text.push('\n');
+        text.push_str(line);
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("not an EE.log entry: {0:?}")]
+pub struct LogParseError(pub String);
+
+impl FromStr for LogEntry {
+    type Err = LogParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<LogLine>().map(|x| x.entry)
+    }
+}
+
+/// An EE.log entry and when it was logged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    /// since the game started, as the line says. the odd line without one
+    /// doesn't have it
+    pub since_start: Option<Duration>,
+    /// `since_start` anchored to wall-clock time by the watcher, or when it
+    /// read the line if it has no `since_start`. None when just parsed
+    pub at: Option<DateTime<Utc>>,
+    pub entry: LogEntry,
+}
+
+/// `9999.999` seconds, exactly
+fn parse_since_start(s: &str) -> Option<Duration> {
+    let (secs, fraction) = s.split_once('.')?;
+    let nanos = format!("{fraction:0<9}");
+    Some(Duration::new(
+        secs.parse().ok()?,
+        nanos.get(..9)?.parse().ok()?,
+    ))
+}
+
+/// Whether `line` starts an entry of its own. Anything else (indented, or
+/// without `system [level]: `) continues the one before it: stack traces,
+/// mission reports, script dumps.
+pub fn is_header(line: &str) -> bool {
+    !line.starts_with(char::is_whitespace) && LogEntryParser::new().is_match(line.trim_end())
+}
+
+impl FromStr for LogLine {
+    type Err = LogParseError;
+
+    /// a header line, optionally followed by its continuation lines
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.trim_start().lines();
+        let header = lines.next().unwrap_or_default().trim();
+        let captures = LogEntryParser::new()
+            .captures(header)
+            .ok_or_else(|| LogParseError(header.to_string()))?;
+        let mut entry = LogEntry::new(
+            captures.system.content.trim().into(),
+            captures.level.content.trim().into(),
+            captures.rest.content.trim(),
+        );
+        lines.for_each(|x| entry.continue_with(x));
+        Ok(Self {
+            since_start: captures.time.and_then(|x| parse_since_start(x.content)),
+            at: None,
+            entry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_game_time() {
+        let line = "9999.999 Net [Info]: Num session players: 2"
+            .parse::<LogLine>()
+            .unwrap();
+        assert_eq!(line.since_start, Some(Duration::from_millis(9999999)));
+        assert_eq!(line.at, None);
+        assert_eq!(
+            line.entry,
+            LogEntry::NetInfo("Num session players: 2".to_string())
+        );
+        let line = "Sys [Info]: no time".parse::<LogLine>().unwrap();
+        assert_eq!(line.since_start, None);
+        assert_eq!(parse_since_start("1.5"), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn unforeseen_entries() {
+        assert_eq!(
+            "12.000 Net [Warning]: packet loss".parse(),
+            Ok(LogEntry::Other {
+                system: System::Net,
+                level: Level::Warning,
+                message: "packet loss".to_string(),
+            })
+        );
+        assert_eq!(
+            "12.000 Lotus [Debug]: hello".parse(),
+            Ok(LogEntry::Other {
+                system: System::Other("Lotus".to_string()),
+                level: Level::Other("Debug".to_string()),
+                message: "hello".to_string(),
+            })
+        );
+        // a script line that isn't from a .lua file
+        assert!(matches!(
+            "Script [Info]: something else".parse(),
+            Ok(LogEntry::Other {
+                system: System::Script,
+                ..
+            })
+        ));
+        assert_eq!(System::AI.to_string(), "AI");
+        assert_eq!(Level::Other("Debug".to_string()).to_string(), "Debug");
+        assert_eq!(
+            "  continued from above".parse::<LogEntry>(),
+            Err(LogParseError("continued from above".to_string()))
+        );
+    }
+}
@@ -1,130 +1,438 @@
-use std::{ffi::OsStr, io::SeekFrom, path::PathBuf, str::FromStr, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use ctreg::regex;
+use chrono::{DateTime, TimeDelta, Utc};
+use discovery::candidate_paths;
+use records::Records;
 use tokio::{
     fs::File,
-    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
-    time::sleep,
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc::{Receiver, Sender},
+    time::{sleep, timeout},
 };
 use tracing::*;
+use wakeups::Wakeups;
 
-#[cfg(target_os = "linux")]
-static DEFAULT_PATH: [&str; 14] = [
-    ".local",
-    "share",
-    "Steam",
-    "steamapps",
-    "compatdata",
-    "230410",
-    "pfx",
-    "drive_c",
-    "users",
-    "steamuser",
-    "AppData",
-    "Local",
-    "Warframe",
-    "EE.log",
-];
-#[cfg(target_os = "windows")]
-static DEFAULT_PATH: [&str; 3] = ["AppData", "Local", "Warframe"];
-
-pub fn get_default_path() -> PathBuf {
-    dirs::home_dir()
-        .unwrap()
-        .iter()
-        .chain(DEFAULT_PATH.iter().map(OsStr::new))
-        .collect()
-}
+pub mod discovery;
+pub mod entry;
+mod records;
+pub mod replay;
+mod wakeups;
 
-regex! { LogEntryParser = r#"(?:\d+\.\d+ )?(?<system>[A-z]+) \[(?<level>[A-z]+)\]: (?<rest>.*)"# }
-regex! { LogScriptEntryParser = r#"(?<script>[A-z]+)\.lua: (?<rest>.*)"# }
+pub use entry::{LogEntry, LogLine, LogParseError};
 
-#[derive(Debug)]
-pub enum LogEntry {
-    SysInfo(String),
-    SysWarning(String),
-    SysError(String),
-    NetInfo(String),
-    NetError(String),
-    PhysInfo(String),
-    PhysWarning(String),
-    PhysError(String),
-    SndInfo(String),
-    GfxInfo(String),
-    InputInfo(String),
-    AIInfo(String),
-    GameInfo(String),
-    GameWarning(String),
-    AnimInfo(String),
-    ScriptInfo { script: String, content: String },
+/// how often a missing EE.log is looked for again, and how often EE.log is
+/// checked when filesystem events say nothing happened
+const LOG_SEARCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// how often EE.log is read when there are no filesystem events to wait for
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// how long the log has to stay quiet before its last entry counts as
+/// complete, with no more continuation lines coming. single-line entries
+/// don't wait
+const CONTINUATION_WAIT: Duration = Duration::from_millis(50);
+
+/// When the game started in wall-clock time, going by the lines read so far.
+#[derive(Debug, Default)]
+struct GameClock {
+    started: Option<DateTime<Utc>>,
 }
 
-impl FromStr for LogEntry {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.trim();
-        let captures = LogEntryParser::new().captures(s).ok_or(())?;
-        let system = captures.system.content.trim();
-        let level = captures.level.content.trim();
-        let rest = captures.rest.content.trim();
-        match (system, level) {
-            ("Sys", "Info") => Ok(Self::SysInfo(rest.to_string())),
-            ("Sys", "Warning") => Ok(Self::SysWarning(rest.to_string())),
-            ("Sys", "Error") => Ok(Self::SysError(rest.to_string())),
-            ("Net", "Info") => Ok(Self::NetInfo(rest.to_string())),
-            ("Net", "Error") => Ok(Self::NetError(rest.to_string())),
-            ("Phys", "Info") => Ok(Self::PhysInfo(rest.to_string())),
-            ("Phys", "Warning") => Ok(Self::PhysWarning(rest.to_string())),
-            ("Phys", "Error") => Ok(Self::PhysError(rest.to_string())),
-            ("Snd", "Info") => Ok(Self::SndInfo(rest.to_string())),
-            ("Gfx", "Info") => Ok(Self::GfxInfo(rest.to_string())),
-            ("Input", "Info") => Ok(Self::InputInfo(rest.to_string())),
-            ("AI", "Info") => Ok(Self::AIInfo(rest.to_string())),
-            ("Game", "Info") => Ok(Self::GameInfo(rest.to_string())),
-            ("Game", "Warning") => Ok(Self::GameWarning(rest.to_string())),
-            ("Anim", "Info") => Ok(Self::AnimInfo(rest.to_string())),
-            ("Script", "Info") => {
-                let captures = LogScriptEntryParser::new().captures(rest).ok_or(())?;
-                Ok(Self::ScriptInfo {
-                    script: captures.script.content.trim().to_string(),
-                    content: captures.rest.content.trim().to_string(),
-                })
+impl GameClock {
+    /// Fill in when `line` was logged, having read it `now`. A line is only
+    /// ever read after it was written, so the earliest start it suggests is
+    /// the closest one; reading late doesn't move it.
+    fn stamp(&mut self, line: &mut LogLine, now: DateTime<Utc>) {
+        let Some(since_start) = line.since_start.and_then(|x| TimeDelta::from_std(x).ok()) else {
+            line.at = Some(now);
+            return;
+        };
+        let started = match self.started {
+            Some(started) => started.min(now - since_start),
+            None => now - since_start,
+        };
+        self.started = Some(started);
+        line.at = Some(started + since_start);
+    }
+}
+
+/// `explicit` if given, otherwise the first of [`candidate_paths`] that
+/// exists. Waits for the game to create it, listing where it looked once.
+/// The flag is whether it had to wait, i.e. the log is a new one.
+async fn find_log(explicit: Option<PathBuf>) -> (PathBuf, bool) {
+    let mut reported = false;
+    let mut watching: Option<(Vec<PathBuf>, Wakeups)> = None;
+    loop {
+        let candidates = match &explicit {
+            Some(path) => vec![path.clone()],
+            None => candidate_paths(),
+        };
+        for path in &candidates {
+            if tokio::fs::metadata(path).await.is_ok_and(|x| x.is_file()) {
+                event!(Level::INFO, "watching {path:?}");
+                return (path.clone(), reported);
             }
-            _ => Err(()),
+        }
+        if !reported {
+            let tried = candidates
+                .iter()
+                .map(|x| format!("\n  {}", x.display()))
+                .collect::<String>();
+            match &explicit {
+                Some(_) => event!(Level::WARN, "EE_LOG_PATH doesn't exist yet, waiting:{tried}"),
+                None if candidates.is_empty() => event!(
+                    Level::WARN,
+                    "no home directory to look for EE.log in, set EE_LOG_PATH"
+                ),
+                None => event!(
+                    Level::WARN,
+                    "EE.log not found, waiting for the game to create it; set EE_LOG_PATH if it's somewhere else. tried:{tried}"
+                ),
+            }
+            reported = true;
+        }
+        // the game creating EE.log in a directory that exists shows up right
+        // away, anything else on the next look
+        if watching
+            .as_ref()
+            .is_none_or(|(watched, _)| *watched != candidates)
+        {
+            let wakeups = Wakeups::new(&candidates, LOG_SEARCH_INTERVAL, LOG_SEARCH_INTERVAL);
+            watching = Some((candidates, wakeups));
+        }
+        if let Some((_, wakeups)) = &mut watching {
+            wakeups.wait().await;
         }
     }
 }
 
-pub async fn watcher() -> tokio::sync::mpsc::Receiver<LogEntry> {
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
-    tokio::spawn(async move {
-        let path = get_default_path();
-        let mut file = loop {
-            match File::open(&path).await {
-                Ok(f) => break BufReader::new(f),
-                Err(_) => {
-                    event!(Level::INFO, "EE.log not found yet, waiting");
-                    sleep(Duration::from_secs(5)).await;
+/// What the watcher sends.
+#[derive(Debug)]
+pub enum LogEvent {
+    Entry(LogLine),
+    /// an entry that was in EE.log before the watcher got to it: what it
+    /// says about the game (who's logged in, which mission) still holds,
+    /// but whatever it announced is over. all of them come before the first
+    /// `Entry`
+    Backlog(LogLine),
+    /// EE.log was recreated or truncated, so the game restarted; whatever
+    /// was known about the last session is stale. Entries of the new log
+    /// follow from its start.
+    Restarted,
+}
+
+/// what tells a recreated EE.log from the one already open. only unix has
+/// it; elsewhere just truncation is noticed
+#[cfg(unix)]
+fn file_id(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// EE.log opened for reading, and how far into it that got.
+struct Tail {
+    file: BufReader<File>,
+    id: Option<(u64, u64)>,
+    offset: u64,
+}
+
+impl Tail {
+    /// open `path` at its start; retries until it can
+    async fn open(path: &Path) -> Self {
+        loop {
+            match Self::try_open(path).await {
+                Ok(tail) => return tail,
+                Err(e) => {
+                    event!(Level::WARN, "can't open {path:?}, retrying: {e}");
+                    sleep(LOG_SEARCH_INTERVAL).await;
                 }
             }
-        };
-        file.seek(SeekFrom::End(0)).await.unwrap();
-        let mut buffer = Vec::with_capacity(50);
+        }
+    }
+
+    async fn try_open(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path).await?;
+        let id = file_id(&file.metadata().await?);
+        Ok(Self {
+            file: BufReader::new(file),
+            id,
+            offset: 0,
+        })
+    }
+
+    async fn len(&self) -> u64 {
+        match self.file.get_ref().metadata().await {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        }
+    }
+
+    /// whether the file at `path` isn't the one open anymore, or shrank below
+    /// what's been read. a missing file doesn't count yet, the game deletes
+    /// the old log before it creates the new one
+    async fn replaced(&self, path: &Path) -> bool {
+        match tokio::fs::metadata(path).await {
+            Ok(meta) => file_id(&meta) != self.id || meta.len() < self.offset,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Where the engine reads EE.log from: the live game, or a saved log.
+pub trait LogSource: Send + 'static {
+    /// start reading in the background, sending what's read
+    fn start(self) -> Receiver<LogEvent>;
+}
+
+/// The game's EE.log as it's written, at `path` if given, otherwise
+/// wherever [`candidate_paths`] finds it.
+#[derive(Debug, Clone, Default)]
+pub struct LiveLog {
+    pub path: Option<PathBuf>,
+}
+
+/// Tail EE.log, at `path` if given, otherwise wherever [`candidate_paths`]
+/// finds it. Follows it through the game recreating or truncating it.
+pub async fn watcher(path: Option<PathBuf>) -> Receiver<LogEvent> {
+    LiveLog { path }.start()
+}
+
+impl LogSource for LiveLog {
+    fn start(self) -> Receiver<LogEvent> {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(tail_log(self.path, tx));
+        rx
+    }
+}
+
+async fn tail_log(path: Option<PathBuf>, tx: Sender<LogEvent>) {
+    let (path, created) = find_log(path).await;
+    // watching before the first read, so no write is missed in between
+    let mut wakeups = Wakeups::new(
+        std::slice::from_ref(&path),
+        LOG_SEARCH_INTERVAL,
+        POLL_INTERVAL,
+    );
+    let mut tail = Tail::open(&path).await;
+    // what a session that was already running logged so far is backlog, a
+    // log the game just created is all news
+    let mut backlog = match created {
+        true => 0,
+        false => tail.len().await,
+    };
+    // entries ending by `backlog` are from before
+    let event = |line, end, backlog| match end <= backlog {
+        true => LogEvent::Backlog(line),
+        false => LogEvent::Entry(line),
+    };
+    // a line being written is kept here until its newline shows up
+    let mut buffer = Vec::with_capacity(50);
+    let mut records = Records::default();
+    loop {
+        let mut quiet = true;
         loop {
-            loop {
-                file.read_until(b'\n', &mut buffer).await.unwrap();
-                if buffer.last() != Some(&b'\n') {
+            // where the entry this line completes ended
+            let end = tail.offset - buffer.len() as u64;
+            match tail.file.read_until(b'\n', &mut buffer).await {
+                Ok(read) => {
+                    tail.offset += read as u64;
+                    quiet &= read == 0;
+                }
+                Err(e) => {
+                    event!(Level::WARN, "reading {path:?} failed: {e}");
                     break;
                 }
-                let str = String::from_utf8_lossy(&buffer).into_owned();
-                buffer.clear();
-                if let Ok(entry) = str.parse() {
-                    tx.send(entry).await.unwrap();
+            }
+            if buffer.last() != Some(&b'\n') {
+                break;
+            }
+            let str = String::from_utf8_lossy(&buffer).into_owned();
+            buffer.clear();
+            if let Some(line) = records.push(&str, Utc::now()) {
+                if tx.send(event(line, end, backlog)).await.is_err() {
+                    return;
+                }
+            }
+            if let Some(line) = records.complete() {
+                if tx.send(event(line, tail.offset, backlog)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        if quiet {
+            let end = tail.offset - buffer.len() as u64;
+            if let Some(line) = records.flush(&buffer) {
+                if tx.send(event(line, end, backlog)).await.is_err() {
+                    return;
                 }
             }
-            sleep(Duration::from_millis(100)).await;
         }
-    });
-    rx
+        if tail.replaced(&path).await {
+            event!(
+                Level::INFO,
+                "{path:?} was recreated or truncated, game restarted"
+            );
+            // the old log's last entry is as complete as it gets, a
+            // partial line left over is dropped with it
+            buffer.clear();
+            let last = std::mem::take(&mut records).finish();
+            if let Some(line) = last {
+                if tx.send(event(line, tail.offset, backlog)).await.is_err() {
+                    return;
+                }
+            }
+            tail = Tail::open(&path).await;
+            backlog = 0;
+            if tx.send(LogEvent::Restarted).await.is_err() {
+                return;
+            }
+            continue;
+        }
+        match records.is_pending() {
+            true => {
+                let _ = timeout(CONTINUATION_WAIT, wakeups.wait()).await;
+            }
+            false => wakeups.wait().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    const LINE: &str = "9999.999 Net [Info]: Num session players: 2\n";
+
+    async fn next(receiver: &mut Receiver<LogEvent>) -> LogEvent {
+        timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("watcher sent nothing")
+            .unwrap()
+    }
+
+    fn append(path: &Path, text: &str) {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn anchors_game_time_to_the_earliest_read() {
+        let now = Utc::now();
+        let line = |secs| LogLine {
+            since_start: Some(Duration::from_secs(secs)),
+            at: None,
+            entry: LogEntry::SysInfo(String::new()),
+        };
+        let mut clock = GameClock::default();
+        let mut first = line(10);
+        clock.stamp(&mut first, now);
+        assert_eq!(first.at, Some(now));
+        // read 5s late: still logged 2s after the first one
+        let mut late = line(12);
+        clock.stamp(&mut late, now + TimeDelta::seconds(7));
+        assert_eq!(late.at, Some(now + TimeDelta::seconds(2)));
+        // read sooner than the first one was, the start moves earlier
+        let mut sooner = line(20);
+        clock.stamp(&mut sooner, now + TimeDelta::seconds(9));
+        assert_eq!(sooner.at, Some(now + TimeDelta::seconds(9)));
+
+        let mut untimed = LogLine {
+            since_start: None,
+            ..line(0)
+        };
+        clock.stamp(&mut untimed, now);
+        assert_eq!(untimed.at, Some(now));
+    }
+
+    #[tokio::test]
+    async fn follows_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("EE.log");
+        std::fs::write(&path, "0.000 Sys [Info]: an old session\n".repeat(10)).unwrap();
+        let mut receiver = watcher(Some(path.clone())).await;
+        // the session so far is read, but as backlog
+        for _ in 0..10 {
+            assert!(matches!(next(&mut receiver).await, LogEvent::Backlog(_)));
+        }
+        sleep(Duration::from_millis(300)).await;
+
+        append(&path, LINE);
+        assert!(matches!(
+            next(&mut receiver).await,
+            LogEvent::Entry(LogLine { entry: LogEntry::NetInfo(x), at: Some(_), .. })
+                if x == "Num session players: 2"
+        ));
+
+        // truncated and rewritten shorter
+        std::fs::write(&path, LINE).unwrap();
+        assert!(matches!(next(&mut receiver).await, LogEvent::Restarted));
+        assert!(matches!(next(&mut receiver).await, LogEvent::Entry(_)));
+
+        // deleted and created again, bigger than before
+        std::fs::remove_file(&path).unwrap();
+        sleep(Duration::from_millis(300)).await;
+        std::fs::write(&path, LINE.repeat(20)).unwrap();
+        assert!(matches!(next(&mut receiver).await, LogEvent::Restarted));
+        for _ in 0..20 {
+            assert!(matches!(next(&mut receiver).await, LogEvent::Entry(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn reassembles_partial_and_multiline_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("EE.log");
+        std::fs::write(&path, "").unwrap();
+        let mut receiver = watcher(Some(path.clone())).await;
+        sleep(Duration::from_millis(300)).await;
+
+        append(&path, "12.000 Sys [Error]: half");
+        sleep(Duration::from_millis(300)).await;
+        append(&path, " done\r\n  at Foo.lua:1\r\n");
+        sleep(Duration::from_millis(300)).await;
+        append(&path, "  at Bar.lua:2\r\n");
+        assert!(matches!(
+            next(&mut receiver).await,
+            LogEvent::Entry(LogLine { entry: LogEntry::SysError(x), .. })
+                if x == "half done\n  at Foo.lua:1"
+        ));
+        // too late to join the entry before, dropped with a warning
+        append(&path, LINE);
+        assert!(matches!(
+            next(&mut receiver).await,
+            LogEvent::Entry(LogLine {
+                entry: LogEntry::NetInfo(_),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn notices_the_log_being_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("EE.log");
+        let mut receiver = watcher(Some(path.clone())).await;
+        sleep(Duration::from_millis(300)).await;
+
+        // read from its start, and well before the next look would find it
+        std::fs::write(&path, LINE).unwrap();
+        let entry = timeout(LOG_SEARCH_INTERVAL / 2, receiver.recv()).await;
+        assert!(matches!(entry, Ok(Some(LogEvent::Entry(_)))));
+    }
 }
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use tracing::*;
+
+use super::{
+    entry::{is_header, LogLine},
+    GameClock, LogEntry,
+};
+
+/// scripts whose entries are one line each, so they're complete as soon as
+/// they're read. the reward screen's is waited on
+const SINGLE_LINE_SCRIPTS: [&str; 1] = ["ProjectionRewardChoice"];
+
+/// Puts EE.log back together into entries, one complete line at a time:
+/// continuation lines join the entry before them, so an entry is only
+/// complete once the next one starts, or the log goes quiet.
+#[derive(Debug, Default)]
+pub(super) struct Records {
+    clock: GameClock,
+    pending: Option<LogLine>,
+    /// the last entry was let go of before the next one started, anything
+    /// continuing it comes too late
+    let_go: bool,
+}
+
+impl Records {
+    /// a whole line was read `now`; returns the entry it completes, if any
+    pub(super) fn push(&mut self, line: &str, now: DateTime<Utc>) -> Option<LogLine> {
+        if !is_header(line) {
+            match &mut self.pending {
+                Some(pending) => pending.entry.continue_with(line),
+                None if line.trim().is_empty() => {}
+                None if self.let_go => event!(
+                    Level::WARN,
+                    "continuation of an entry that was already sent, dropped: {line:?}"
+                ),
+                None => event!(Level::TRACE, "continuation without an entry: {line:?}"),
+            }
+            return None;
+        }
+        let mut header = line.parse::<LogLine>().ok()?;
+        self.clock.stamp(&mut header, now);
+        self.let_go = false;
+        self.pending.replace(header)
+    }
+
+    /// the pending entry if nothing is ever logged under it, without waiting
+    /// for the next one or the log going quiet
+    pub(super) fn complete(&mut self) -> Option<LogLine> {
+        let single_line = self.pending.as_ref().is_some_and(|x| {
+            matches!(&x.entry, LogEntry::ScriptInfo { script, .. }
+                if SINGLE_LINE_SCRIPTS.contains(&script.as_str()))
+        });
+        match single_line {
+            true => self.let_go(),
+            false => None,
+        }
+    }
+
+    pub(super) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Nothing more was written for now, so the pending entry is complete,
+    /// unless `partial`, a line still being written, is going to continue it.
+    pub(super) fn flush(&mut self, partial: &[u8]) -> Option<LogLine> {
+        match partial.first() {
+            Some(x) if x.is_ascii_whitespace() => None,
+            _ => self.let_go(),
+        }
+    }
+
+    fn let_go(&mut self) -> Option<LogLine> {
+        let pending = self.pending.take()?;
+        self.let_go = true;
+        Some(pending)
+    }
+
+    /// the pending entry as it is, the log it came from is gone
+    pub(super) fn finish(self) -> Option<LogLine> {
+        self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_continuation_lines() {
+        let now = Utc::now();
+        let mut records = Records::default();
+        assert_eq!(records.push("  nothing to continue\n", now), None);
+        assert_eq!(
+            records.push("10.000 Sys [Error]: Script error:\r\n", now),
+            None
+        );
+        assert_eq!(records.push("  at Foo.lua:12\r\n", now), None);
+        assert_eq!(records.push("\r\n", now), None);
+        assert_eq!(records.push("\tat Bar.lua:3\r\n", now), None);
+        let grouped = records
+            .push("11.000 Net [Info]: Num session players: 2\r\n", now)
+            .unwrap();
+        assert_eq!(
+            grouped.entry,
+            LogEntry::SysError("Script error:\n  at Foo.lua:12\n\tat Bar.lua:3".to_string())
+        );
+        assert!(grouped.at.is_some());
+
+        // a continuation is still being written
+        assert_eq!(records.flush(b"  at "), None);
+        assert_eq!(
+            records.flush(b"").map(|x| x.entry),
+            Some(LogEntry::NetInfo("Num session players: 2".to_string()))
+        );
+        assert!(!records.is_pending());
+    }
+
+    #[test]
+    fn headers_only_start_a_line() {
+        let now = Utc::now();
+        let mut records = Records::default();
+        records.push("10.000 Sys [Info]: Mission report:\r\n", now);
+        // a header somewhere in the line, and one that's not quite a header
+        assert_eq!(
+            records.push("Totals: Net [Info]: 12 packets\r\n", now),
+            None
+        );
+        assert_eq!(records.push("players_seen [All]: Tenno\r\n", now), None);
+        let report = records
+            .push("11.000 Net [Info]: Num session players: 2\r\n", now)
+            .unwrap();
+        assert_eq!(
+            report.entry,
+            LogEntry::SysInfo(
+                "Mission report:\nTotals: Net [Info]: 12 packets\nplayers_seen [All]: Tenno"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn single_line_entries_are_complete_right_away() {
+        let now = Utc::now();
+        let mut records = Records::default();
+        records.push("10.000 Sys [Info]: Level loaded\r\n", now);
+        // nothing tells a Sys entry is done yet
+        assert_eq!(records.complete(), None);
+        let reward_screen =
+            "11.000 Script [Info]: ProjectionRewardChoice.lua: Relic rewards initialized\r\n";
+        assert!(records.push(reward_screen, now).is_some());
+        assert_eq!(
+            records.complete().map(|x| x.entry),
+            Some(LogEntry::ScriptInfo {
+                script: "ProjectionRewardChoice".to_string(),
+                content: "Relic rewards initialized".to_string(),
+            })
+        );
+        // too late to join it, dropped
+        assert_eq!(records.push("  at Foo.lua:1\r\n", now), None);
+        assert!(!records.is_pending());
+    }
+}
@@ -0,0 +1,236 @@
+use std::{path::PathBuf, str::FromStr};
+
+use chrono::Utc;
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time::{sleep_until, Instant},
+};
+use tracing::*;
+
+use super::{entry::LogLine, records::Records, GameClock, LogEvent, LogSource};
+
+/// How fast a [`Replay`] goes through its log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// everything at once, as fast as it's taken
+    Instant,
+    /// at the pace the entries were logged, this many times faster; 1 is
+    /// real time
+    Times(f64),
+}
+
+impl Default for ReplaySpeed {
+    fn default() -> Self {
+        Self::Times(1.0)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown replay speed {0:?}, expected instant or a factor like 4x")]
+pub struct UnknownReplaySpeed(String);
+
+impl FromStr for ReplaySpeed {
+    type Err = UnknownReplaySpeed;
+
+    /// `instant`, or a factor: `1x`, `4x`, `0.5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if s == "instant" {
+            return Ok(Self::Instant);
+        }
+        s.strip_suffix('x')
+            .unwrap_or(&s)
+            .parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite() && *x > 0.0)
+            .map(Self::Times)
+            .ok_or_else(|| UnknownReplaySpeed(s.to_string()))
+    }
+}
+
+/// A saved EE.log, fed to the engine as if the game was writing it right
+/// now: same entries, same grouping, and with [`ReplaySpeed::Times`] the
+/// same pacing. Entries' `at` is when they're replayed. Game time going
+/// backwards means the log was appended to after a restart, and replays as
+/// [`LogEvent::Restarted`].
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub path: PathBuf,
+    pub speed: ReplaySpeed,
+}
+
+impl LogSource for Replay {
+    fn start(self) -> Receiver<LogEvent> {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(self.replay(tx));
+        rx
+    }
+}
+
+/// every entry of a whole log, put together by the same [`Records`] the
+/// live tail uses
+fn entries(text: &str) -> Vec<LogLine> {
+    let now = Utc::now();
+    let mut records = Records::default();
+    let mut entries = text
+        .split_inclusive('\n')
+        .filter_map(|line| records.push(line, now))
+        .collect::<Vec<_>>();
+    entries.extend(records.finish());
+    entries
+}
+
+impl Replay {
+    async fn replay(self, tx: Sender<LogEvent>) {
+        let text = match tokio::fs::read(&self.path).await {
+            Ok(x) => x,
+            Err(e) => {
+                event!(Level::WARN, "can't replay {:?}: {e}", self.path);
+                return;
+            }
+        };
+        let entries = entries(&String::from_utf8_lossy(&text));
+        event!(
+            Level::INFO,
+            "replaying {} entries of {:?} at {:?}",
+            entries.len(),
+            self.path,
+            self.speed
+        );
+        let mut started = Instant::now();
+        let mut first = None;
+        let mut last = None;
+        let mut clock = GameClock::default();
+        for mut line in entries {
+            if let Some(since_start) = line.since_start {
+                if last.is_some_and(|last| since_start < last) {
+                    event!(Level::INFO, "replayed log restarts at {since_start:?}");
+                    if tx.send(LogEvent::Restarted).await.is_err() {
+                        return;
+                    }
+                    // the new game is paced and dated from its own start
+                    started = Instant::now();
+                    first = None;
+                    clock = GameClock::default();
+                }
+                last = Some(since_start);
+                if let ReplaySpeed::Times(factor) = self.speed {
+                    let first = *first.get_or_insert(since_start);
+                    sleep_until(started + since_start.saturating_sub(first).div_f64(factor)).await;
+                }
+            }
+            clock.stamp(&mut line, Utc::now());
+            if tx.send(LogEvent::Entry(line)).await.is_err() {
+                return;
+            }
+        }
+        event!(Level::INFO, "replay of {:?} done", self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::log_watcher::LogEntry;
+
+    use super::*;
+
+    const LOG: &str = "  left over from before\r\n\
+        10.000 Net [Info]: Num session players: 2\r\n\
+        10.200 Sys [Error]: Script error:\r\n  at Foo.lua:12\r\n\
+        10.400 Script [Info]: ProjectionRewardChoice.lua: Relic rewards initialized\r\n";
+
+    async fn replay(log: &str, speed: ReplaySpeed) -> (Vec<LogEvent>, Duration) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("EE.log");
+        std::fs::write(&path, log).unwrap();
+        let started = Instant::now();
+        let mut receiver = Replay { path, speed }.start();
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+        (events, started.elapsed())
+    }
+
+    #[test]
+    fn parses_speeds() {
+        assert_eq!(
+            "instant".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Instant
+        );
+        assert_eq!(
+            "4x".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Times(4.0)
+        );
+        assert_eq!(
+            "0.5".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Times(0.5)
+        );
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[tokio::test]
+    async fn replays_like_the_live_tail() {
+        let (events, elapsed) = replay(LOG, ReplaySpeed::Instant).await;
+        assert!(elapsed < Duration::from_millis(200));
+        let entries = events
+            .into_iter()
+            .map(|x| match x {
+                LogEvent::Entry(line) => line.entry,
+                x => panic!("a replay only has entries, got {x:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                LogEntry::NetInfo("Num session players: 2".to_string()),
+                LogEntry::SysError("Script error:\n  at Foo.lua:12".to_string()),
+                LogEntry::ScriptInfo {
+                    script: "ProjectionRewardChoice".to_string(),
+                    content: "Relic rewards initialized".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_the_log_pacing() {
+        let (events, elapsed) = replay(LOG, ReplaySpeed::Times(1.0)).await;
+        assert_eq!(events.len(), 3);
+        assert!(elapsed >= Duration::from_millis(400));
+
+        let (_, elapsed) = replay(LOG, ReplaySpeed::Times(4.0)).await;
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn game_time_going_back_is_a_restart() {
+        let log = "500.000 Net [Info]: Num session players: 2\n\
+            500.300 Net [Info]: Num session players: 3\n\
+            1.000 Net [Info]: Num session players: 1\n\
+            1.200 Net [Info]: Num session players: 4\n";
+        let (events, elapsed) = replay(log, ReplaySpeed::Times(1.0)).await;
+        let players =
+            |count: &str| Some(LogEntry::NetInfo(format!("Num session players: {count}")));
+        // None for the restart
+        let events = events
+            .into_iter()
+            .map(|x| match x {
+                LogEvent::Entry(line) => Some(line.entry),
+                LogEvent::Restarted => None,
+                x => panic!("a replay has no backlog, got {x:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [players("2"), players("3"), None, players("1"), players("4")]
+        );
+        // 300ms before the restart and 200ms after, not 500s
+        assert!(elapsed >= Duration::from_millis(500));
+        assert!(elapsed < Duration::from_secs(2));
+    }
+}
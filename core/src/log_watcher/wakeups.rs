@@ -0,0 +1,123 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{sync::mpsc, time::sleep};
+use tracing::*;
+
+/// Wakes the watcher when something may have happened to a file: filesystem
+/// events on its directory (inotify on linux) when the platform has them,
+/// and a timer either way, for the events that never come (network mounts,
+/// directories that don't exist yet).
+pub(super) struct Wakeups {
+    /// kept alive for as long as events are wanted
+    _watcher: Option<RecommendedWatcher>,
+    /// None without a watcher; its sender went with it, and a closed
+    /// channel would wake every time
+    events: Option<mpsc::Receiver<()>>,
+    timer: Duration,
+}
+
+impl Wakeups {
+    /// Watch the directories of `files`, waking up for events on any of
+    /// them. With at least one directory watched, `timer` is how long to
+    /// wait without an event; otherwise `fallback` is.
+    pub(super) fn new(files: &[PathBuf], timer: Duration, fallback: Duration) -> Self {
+        // one pending wakeup is as good as many, the reader catches up on
+        // everything at once
+        let (sender, events) = mpsc::channel(1);
+        let names = files
+            .iter()
+            .flat_map(|x| x.file_name())
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let relevant = event.is_ok_and(|event| {
+                event
+                    .paths
+                    .iter()
+                    .any(|x| x.file_name().is_some_and(|x| names.iter().any(|n| n == x)))
+            });
+            if relevant {
+                let _ = sender.try_send(());
+            }
+        });
+        let watcher = match watcher {
+            Ok(mut watcher) => {
+                let mut dirs = files.iter().flat_map(|x| x.parent()).collect::<Vec<_>>();
+                dirs.sort();
+                dirs.dedup();
+                let watched = dirs
+                    .into_iter()
+                    .filter(|dir| watch(&mut watcher, dir))
+                    .count();
+                (watched > 0).then_some(watcher)
+            }
+            Err(e) => {
+                event!(Level::WARN, "no filesystem events, polling instead: {e}");
+                None
+            }
+        };
+        let (timer, events) = match watcher {
+            Some(_) => (timer, Some(events)),
+            None => (fallback, None),
+        };
+        Self {
+            _watcher: watcher,
+            events,
+            timer,
+        }
+    }
+
+    /// until the next event or the timer runs out, whichever is first
+    pub(super) async fn wait(&mut self) {
+        let Some(events) = &mut self.events else {
+            sleep(self.timer).await;
+            return;
+        };
+        tokio::select! {
+            _ = events.recv() => {}
+            _ = sleep(self.timer) => {}
+        }
+    }
+}
+
+/// a directory that doesn't exist (yet) just isn't watched
+fn watch(watcher: &mut RecommendedWatcher, dir: &Path) -> bool {
+    if !dir.is_dir() {
+        return false;
+    }
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .inspect_err(|e| event!(Level::WARN, "can't watch {dir:?}: {e}"))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn nothing_to_watch_waits_for_the_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not/there/yet/EE.log");
+        let fallback = Duration::from_millis(200);
+        let mut wakeups = Wakeups::new(&[file], Duration::from_millis(10), fallback);
+        for _ in 0..2 {
+            let started = Instant::now();
+            wakeups.wait().await;
+            assert!(started.elapsed() >= fallback);
+        }
+
+        // no files at all, like without a home directory
+        let mut wakeups = Wakeups::new(&[], Duration::from_millis(10), fallback);
+        let started = Instant::now();
+        wakeups.wait().await;
+        assert!(started.elapsed() >= fallback);
+    }
+}
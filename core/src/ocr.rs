@@ -28,6 +28,5 @@ fn ocr_blocking(img: DynamicImage) -> anyhow::Result<String> {
     let source = ImageSource::from_bytes(img.as_raw(), img.dimensions())?;
 
     let ocr_input = engine.prepare_input(source)?;
-    let result = engine.get_text(&ocr_input);
-    result
+    engine.get_text(&ocr_input)
 }
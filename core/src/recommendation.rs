@@ -0,0 +1,279 @@
+use std::{cmp::Reverse, fmt::Display};
+
+use crate::{
+    event::RewardSlot,
+    items::items::{Item, ItemDatabase},
+    valuation::Valuation,
+};
+
+/// What the recommended pick on a reward screen maximizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Objective {
+    /// the part that sells for the most
+    #[default]
+    MaxPlat,
+    /// the part baro gives the most ducats for
+    MaxDucats,
+    /// a part still missing from the set closest to done
+    SetCompletion,
+    /// the most valuable part you don't have enough of yet
+    OwnedAware,
+}
+
+impl Objective {
+    /// look up an objective by its config name: `max_plat`, `max_ducats`,
+    /// `set_completion` or `owned`
+    pub fn by_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "max_plat" => Objective::MaxPlat,
+            "max_ducats" => Objective::MaxDucats,
+            "set_completion" => Objective::SetCompletion,
+            "owned" => Objective::OwnedAware,
+            _ => return None,
+        })
+    }
+}
+
+/// Why a slot was recommended, for frontends to show next to it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reason {
+    Plat(u32),
+    Ducats(u32),
+    /// owned fewer than the set needs
+    Missing {
+        owned: u32,
+        needed: u32,
+    },
+    /// `missing` parts of `set` left to get after this one
+    SetProgress {
+        set: String,
+        missing: u32,
+    },
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Plat(p) => write!(f, "most plat ({p}p)"),
+            Reason::Ducats(d) => write!(f, "most ducats ({d}d)"),
+            Reason::Missing { owned, needed } => write!(f, "own {owned} of {needed}"),
+            Reason::SetProgress { set, missing: 0 } => write!(f, "completes {set}"),
+            Reason::SetProgress { set, missing: 1 } => write!(f, "{set}, 1 part to go"),
+            Reason::SetProgress { set, missing } => write!(f, "{set}, {missing} parts to go"),
+        }
+    }
+}
+
+struct Candidate<'a> {
+    slot: usize,
+    item: &'a Item,
+    price: Option<u32>,
+    owned: u32,
+}
+
+impl Candidate<'_> {
+    fn needed(&self) -> bool {
+        self.owned < self.item.quantity_for_set
+    }
+}
+
+/// The slot to pick under `valuation`'s objective, `None` if nothing
+/// identified so far is worth anything to it. The objectives that look at
+/// owned parts fall back to the most plat once nothing is missing. Ties go to
+/// the leftmost slot.
+pub fn recommend(
+    slots: &[RewardSlot],
+    db: &ItemDatabase,
+    valuation: &Valuation,
+) -> Option<(usize, Reason)> {
+    let owned = |item: &Item| valuation.owned.get(&item.id_name).copied().unwrap_or(0);
+    let candidates = slots
+        .iter()
+        .enumerate()
+        .flat_map(|(slot, x)| match x {
+            RewardSlot::Item { item, price, .. } => Some(Candidate {
+                slot,
+                item,
+                price: *price,
+                owned: owned(item),
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // max_by_key keeps the last of equal keys, reversing keeps the first
+    let most_plat = || {
+        candidates
+            .iter()
+            .rev()
+            .flat_map(|x| Some((x.slot, x.price?)))
+            .max_by_key(|x| x.1)
+            .map(|(slot, p)| (slot, Reason::Plat(p)))
+    };
+    match valuation.objective {
+        Objective::MaxPlat => most_plat(),
+        Objective::MaxDucats => candidates
+            .iter()
+            .rev()
+            .filter(|x| x.item.ducats > 0)
+            .max_by_key(|x| x.item.ducats)
+            .map(|x| (x.slot, Reason::Ducats(x.item.ducats))),
+        Objective::OwnedAware => candidates
+            .iter()
+            .rev()
+            .filter(|x| x.needed())
+            .max_by_key(|x| x.price.unwrap_or(0))
+            .map(|x| {
+                (
+                    x.slot,
+                    Reason::Missing {
+                        owned: x.owned,
+                        needed: x.item.quantity_for_set,
+                    },
+                )
+            })
+            .or_else(most_plat),
+        Objective::SetCompletion => candidates
+            .iter()
+            .filter(|x| x.needed())
+            .flat_map(|x| {
+                let set = db.set_of(x.item)?;
+                let missing = db
+                    .parts(set)
+                    .map(|part| part.quantity_for_set.saturating_sub(owned(part)))
+                    .sum::<u32>()
+                    .saturating_sub(1);
+                Some((x, set, missing))
+            })
+            .min_by_key(|(x, _, missing)| (*missing, Reverse(x.price.unwrap_or(0))))
+            .map(|(x, set, missing)| {
+                (
+                    x.slot,
+                    Reason::SetProgress {
+                        set: set.name.clone(),
+                        missing,
+                    },
+                )
+            })
+            .or_else(most_plat),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::items::items::ItemSet;
+
+    use super::*;
+
+    fn part(slug: &str, set: &str, ducats: u32, quantity_for_set: u32) -> Item {
+        Item {
+            id: slug.to_string(),
+            id_name: slug.to_string(),
+            name: slug.to_string(),
+            trading_tax: 0,
+            set_id: set.to_string(),
+            ducats,
+            quantity_for_set,
+        }
+    }
+
+    fn slot(item: &Item, price: Option<u32>) -> RewardSlot {
+        RewardSlot::Item {
+            item: item.clone(),
+            price,
+            set: None,
+            ducats: item.ducats,
+            plat_per_ducat: None,
+            best_use: None,
+        }
+    }
+
+    fn db(items: &[&Item]) -> ItemDatabase {
+        let mut db = ItemDatabase::default();
+        for item in items {
+            db.items.insert(item.id.clone(), (*item).clone());
+            db.sets
+                .entry(item.set_id.clone())
+                .or_insert_with(|| ItemSet {
+                    id: item.set_id.clone(),
+                    id_name: item.set_id.clone(),
+                    name: item.set_id.clone(),
+                    part_ids: HashSet::new(),
+                })
+                .part_ids
+                .insert(item.id.clone());
+        }
+        db
+    }
+
+    #[test]
+    fn objectives() {
+        let blade = part("okina_prime_blade", "okina", 25, 2);
+        let handle = part("okina_prime_handle", "okina", 45, 1);
+        let systems = part("shade_prime_systems", "shade", 100, 1);
+        let db = db(&[&blade, &handle, &systems]);
+        let slots = [
+            RewardSlot::Forma,
+            slot(&blade, Some(10)),
+            slot(&handle, None),
+            slot(&systems, Some(10)),
+        ];
+        let pick = |objective, owned: &[(&str, u32)]| {
+            let valuation = Valuation {
+                objective,
+                owned: owned
+                    .iter()
+                    .map(|(slug, n)| (slug.to_string(), *n))
+                    .collect::<HashMap<_, _>>(),
+                ..Default::default()
+            };
+            recommend(&slots, &db, &valuation)
+        };
+
+        // ties go left, unpriced parts don't count
+        assert_eq!(pick(Objective::MaxPlat, &[]), Some((1, Reason::Plat(10))));
+        assert_eq!(
+            pick(Objective::MaxDucats, &[]),
+            Some((3, Reason::Ducats(100)))
+        );
+        assert_eq!(
+            pick(Objective::OwnedAware, &[("okina_prime_blade", 2)]),
+            Some((
+                3,
+                Reason::Missing {
+                    owned: 0,
+                    needed: 1
+                }
+            ))
+        );
+        // okina needs one more blade and the handle, shade just the systems
+        assert_eq!(
+            pick(Objective::SetCompletion, &[("okina_prime_blade", 1)]),
+            Some((
+                3,
+                Reason::SetProgress {
+                    set: "shade".to_string(),
+                    missing: 0
+                }
+            ))
+        );
+        let everything = [
+            ("okina_prime_blade", 2),
+            ("okina_prime_handle", 1),
+            ("shade_prime_systems", 1),
+        ];
+        assert_eq!(
+            pick(Objective::SetCompletion, &everything),
+            Some((1, Reason::Plat(10)))
+        );
+        assert_eq!(
+            recommend(
+                &[RewardSlot::Forma, RewardSlot::Pending],
+                &db,
+                &Default::default()
+            ),
+            None
+        );
+    }
+}
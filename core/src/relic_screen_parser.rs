@@ -2,12 +2,17 @@ use std::collections::{HashMap, HashSet};
 
 use ctreg::regex;
 use futures::stream::{FuturesOrdered, StreamExt};
-use image::DynamicImage;
+use image::{DynamicImage, GenericImageView};
 use tracing::*;
 
 regex! { CapitalFinder = r#"[^$\s](?<capital>[A-Z])"# }
 
-use crate::{debug_write_image, geometry::reward_card_regions, items::items::Item, ocr};
+use crate::{
+    debug_write_image,
+    geometry::{reward_card_regions, CardRegion},
+    items::items::Item,
+    ocr,
+};
 
 #[derive(Debug)]
 pub enum ItemOrForma {
@@ -17,7 +22,7 @@ pub enum ItemOrForma {
 }
 
 #[instrument]
-pub async fn parse_relic_screen<'a>(
+pub async fn parse_relic_screen(
     img: &DynamicImage,
     amount: &HashSet<usize>,
     items: &HashMap<String, Item>,
@@ -116,23 +121,64 @@ pub async fn parse_relic_screen<'a>(
         .await
 }
 
+/// colour of the marker the game draws in the top right corner of the card
+/// the local player has selected
+const SELECTED_MARKER: [u8; 3] = [228, 210, 160];
+
+/// how far off `SELECTED_MARKER` a corner may be, per channel, and still
+/// count as marked
+const SELECTED_MARKER_TOLERANCE: u8 = 30;
+
+/// The slot of the card the local player has selected on a reward screen with
+/// `count` cards, going by the marker in its corner. None unless exactly one
+/// card has it.
+pub fn highlighted_card(img: &DynamicImage, count: usize) -> Option<usize> {
+    // the marker is a triangle; sample a square well inside it, scaled from
+    // 3..9px in from the corner at 1080p
+    let from = (img.height() * 3 / 1080).max(1);
+    let to = (img.height() * 9 / 1080).max(from + 1);
+    let marked = |region: &CardRegion| {
+        let right = region.x + region.width;
+        let mut sums = [0u64; 3];
+        let mut pixels = 0;
+        for dx in from..to {
+            for dy in from..to {
+                let (x, y) = (right - dx, region.top + dy);
+                if !img.in_bounds(x, y) {
+                    return false;
+                }
+                let pixel = img.get_pixel(x, y);
+                for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                    *sum += channel as u64;
+                }
+                pixels += 1;
+            }
+        }
+        sums.iter().zip(SELECTED_MARKER).all(|(sum, marker)| {
+            ((sum / pixels) as u8).abs_diff(marker) <= SELECTED_MARKER_TOLERANCE
+        })
+    };
+    let mut selected = reward_card_regions(img.width(), img.height(), count)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, region)| marked(region))
+        .map(|(i, _)| i);
+    match (selected.next(), selected.next()) {
+        (Some(slot), None) => Some(slot),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{env, path::Path};
-
     use image::ImageReader;
 
-    use crate::items::{cached_get_item_identifiers, cached_items_and_sets};
+    use crate::test_support::mock_database;
 
     use super::*;
 
     async fn assert(img: &DynamicImage, rhs: Vec<Option<String>>) {
-        let cache_path = env::var("CACHE_PATH").unwrap();
-        let cache_path = Path::new(&cache_path);
-        let item_identifiers = cached_get_item_identifiers(cache_path).await.unwrap();
-        let (items, _) = cached_items_and_sets(cache_path, &item_identifiers)
-            .await
-            .unwrap();
+        let items = mock_database().await.items;
         let result = parse_relic_screen(img, &(0..4).collect(), &items)
             .await
             .into_iter()
@@ -272,4 +318,20 @@ mod tests {
         )
         .await;
     }
+
+    #[test]
+    fn highlighted_card_follows_the_corner_marker() {
+        // the game selects the first card until the player picks another
+        for screen in ["1", "2b", "3", "4", "5", "6"] {
+            let img = ImageReader::open(format!("test_rewards_screens/{screen}.png"))
+                .unwrap()
+                .decode()
+                .unwrap();
+            assert_eq!(highlighted_card(&img, 4), Some(0), "screen {screen}");
+        }
+        assert_eq!(
+            highlighted_card(&DynamicImage::new_rgb8(1920, 1080), 4),
+            None
+        );
+    }
 }
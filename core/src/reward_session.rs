@@ -1,19 +1,33 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashSet,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
-use futures::stream::{FuturesOrdered, StreamExt};
+use futures::{
+    future::join_all,
+    stream::{FuturesOrdered, StreamExt},
+};
 use image::DynamicImage;
 use tokio::{
-    sync::mpsc::Sender,
-    time::{sleep, Instant},
+    sync::{mpsc::Sender, oneshot},
+    time::{sleep_until, Instant},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 
 use crate::{
     debug_write_image,
-    event::{Event, RewardSlot},
+    event::{CloseReason, EquippedRelic, Event, RewardSlot, SetContext},
     geometry::WindowRect,
-    items::items::Item,
-    relic_screen_parser::{parse_relic_screen, ItemOrForma},
+    items::{
+        items::{Item, ItemDatabase},
+        price_cache::PriceCache,
+    },
+    log_watcher::LogEntry,
+    recommendation::recommend,
+    relic_screen_parser::{highlighted_card, parse_relic_screen, ItemOrForma},
+    valuation::{plat_per_ducat, Valuation},
 };
 
 /// how long the in-game reward pick window stays on screen
@@ -21,30 +35,196 @@ pub const REWARD_PICK_WINDOW: Duration = Duration::from_secs(15);
 
 const MAX_ATTEMPTS: usize = 10;
 
+/// how often the screen is checked for the cards once OCR is done with them
+const LAYOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// checks in a row that have to miss every card before the screen counts as
+/// gone; one bad frame (a flash, a tooltip) shouldn't close the overlay
+const LAYOUT_GONE_CHECKS: usize = 2;
+
+/// `ProjectionRewardChoice.lua` lines the game logs once the reward screen
+/// closes, whether the local player picked or its timer ran out. none of the
+/// known ones say which card.
+const SHUT_DOWN_LINES: [&str; 1] = ["Relic reward screen shut down"];
+
+/// whether an EE.log entry says the reward screen closed
+pub fn screen_shut_down(entry: &LogEntry) -> bool {
+    matches!(
+        entry,
+        LogEntry::ScriptInfo { script, content }
+            if script == "ProjectionRewardChoice"
+                && SHUT_DOWN_LINES.iter().any(|x| content.starts_with(x))
+    )
+}
+
+/// Ends a reward session from outside, when the game moved on without a
+/// pick. Clones cancel the same session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionCancel {
+    token: CancellationToken,
+    reason: Arc<OnceLock<CloseReason>>,
+}
+
+impl SessionCancel {
+    /// close the session with `reason`; only the first reason counts, and
+    /// cancelling a session that's over already does nothing
+    pub fn cancel(&self, reason: CloseReason) {
+        let _ = self.reason.set(reason);
+        self.token.cancel();
+    }
+
+    async fn cancelled(&self) -> CloseReason {
+        self.token.cancelled().await;
+        *self.reason.get().expect("reason is set before cancelling")
+    }
+}
+
 pub trait CaptureSource: Send + Sync + 'static {
     fn capture(&self) -> anyhow::Result<DynamicImage>;
 }
 
+async fn set_context(item: &Item, db: &ItemDatabase, prices: &PriceCache) -> Option<SetContext> {
+    let set = db.set_of(item)?;
+    let parts = db
+        .parts(set)
+        .map(|part| async move { Some(prices.price(part).await.ok()? * part.quantity_for_set) });
+    let (price, parts_price) = futures::join!(prices.price_of(&set.id_name), join_all(parts));
+    Some(SetContext {
+        name: set.name.clone(),
+        price: price.ok(),
+        parts_price: parts_price.into_iter().sum(),
+        quantity_for_set: item.quantity_for_set,
+    })
+}
+
+/// the slot for `item` with its own price, without its set's yet
+async fn item_slot(item: &Item, prices: &PriceCache, valuation: &Valuation) -> RewardSlot {
+    let price = prices.price(item).await.ok();
+    RewardSlot::Item {
+        item: item.clone(),
+        price,
+        set: None,
+        ducats: item.ducats,
+        plat_per_ducat: price.and_then(|p| plat_per_ducat(p, item.ducats)),
+        best_use: price.map(|p| valuation.best_use(p, item.ducats)),
+    }
+}
+
+/// fill in the set context of every item slot; says whether any had a set
+async fn fill_sets(slots: &mut [RewardSlot], db: &ItemDatabase, prices: &PriceCache) -> bool {
+    let sets = slots
+        .iter()
+        .map(|slot| async move {
+            match slot {
+                RewardSlot::Item { item, .. } => set_context(item, db, prices).await,
+                _ => None,
+            }
+        })
+        .collect::<FuturesOrdered<_>>()
+        .collect::<Vec<_>>()
+        .await;
+    let mut filled = false;
+    for (slot, context) in slots.iter_mut().zip(sets) {
+        if let RewardSlot::Item { set, .. } = slot {
+            filled |= context.is_some();
+            *set = context;
+        }
+    }
+    filled
+}
+
+/// sleep until `deadline` unless `shut_down` fires first, and say whether it
+/// did. `shut_down` is emptied once it's fired or closed, so it's never
+/// polled again
+async fn shut_down_before(
+    shut_down: &mut Option<oneshot::Receiver<()>>,
+    deadline: Instant,
+) -> bool {
+    let Some(receiver) = shut_down else {
+        sleep_until(deadline).await;
+        return false;
+    };
+    tokio::select! {
+        _ = sleep_until(deadline) => false,
+        fired = receiver => {
+            *shut_down = None;
+            match fired {
+                Ok(()) => true,
+                // nobody left to report it, the timer has to do
+                Err(_) => {
+                    sleep_until(deadline).await;
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Run OCR on a reward screen and keep resolving its slots until the pick
+/// window is over, or the screen closes: `shut_down` fires when EE.log says
+/// so, and once OCR is done the screen is checked for the cards going away.
+/// `cancel` ends it right away, whatever it's doing. `relics` are what the
+/// squad cracked, as far as the log knows.
+#[allow(clippy::too_many_arguments)]
 pub async fn run_reward_session(
     capture: &dyn CaptureSource,
-    items: &HashMap<String, Item>,
+    db: &ItemDatabase,
+    prices: &PriceCache,
+    valuation: &Valuation,
     sender: &Sender<Event>,
     count: usize,
     window_rect: Option<WindowRect>,
+    relics: Vec<EquippedRelic>,
     session_duration: Duration,
+    shut_down: oneshot::Receiver<()>,
+    cancel: SessionCancel,
 ) {
-    let started = Instant::now();
+    let deadline = Instant::now() + session_duration;
     let _ = sender
         .send(Event::RewardScreenOpened {
             count,
             window: window_rect,
+            relics,
         })
         .await;
+    let reason = tokio::select! {
+        biased;
+        reason = cancel.cancelled() => {
+            event!(Level::INFO, "reward session cancelled: {reason}");
+            reason
+        }
+        reason = resolve_until_closed(capture, db, prices, valuation, sender, count, deadline, shut_down) => reason,
+    };
+    let _ = sender.send(Event::RewardScreenClosed { reason }).await;
+}
+
+/// the session proper, minus opening and closing it: OCR, then waiting for
+/// the screen to close or the deadline. says why it ended
+#[allow(clippy::too_many_arguments)]
+async fn resolve_until_closed(
+    capture: &dyn CaptureSource,
+    db: &ItemDatabase,
+    prices: &PriceCache,
+    valuation: &Valuation,
+    sender: &Sender<Event>,
+    count: usize,
+    deadline: Instant,
+    shut_down: oneshot::Receiver<()>,
+) -> CloseReason {
+    let mut shut_down = Some(shut_down);
+    let mut closed = None;
+    // the last capture the cards were still on, to tell which was picked
+    let mut last_seen = None;
 
     let mut total_results: Vec<Option<ItemOrForma>> = (0..count).map(|_| None).collect();
+    let mut slots = Vec::new();
     for attempt in 0..MAX_ATTEMPTS {
         event!(Level::INFO, "relic screen run {attempt}");
-        sleep(Duration::from_millis(1000)).await;
+        let next = Instant::now() + Duration::from_millis(1000);
+        if shut_down_before(&mut shut_down, next).await {
+            closed = Some(CloseReason::ShutDown);
+            break;
+        }
         let image = match capture.capture() {
             Ok(img) => img,
             Err(e) => {
@@ -61,7 +241,7 @@ pub async fn run_reward_session(
                 .filter(|(_, x)| x.is_none())
                 .map(|(i, _)| i)
                 .collect(),
-            items,
+            &db.items,
         )
         .await;
         total_results = total_results
@@ -70,23 +250,25 @@ pub async fn run_reward_session(
             .map(|(a, b)| a.or(b))
             .collect();
         let finished = total_results.iter().all(|x| x.is_some());
+        last_seen = Some(image);
 
-        let slots = total_results
+        slots = total_results
             .iter()
             .map(|x| async move {
                 match x {
                     None => RewardSlot::Pending,
                     Some(ItemOrForma::Forma1X) | Some(ItemOrForma::Forma2X) => RewardSlot::Forma,
-                    Some(ItemOrForma::Item(item)) => RewardSlot::Item {
-                        item: item.clone(),
-                        price: item.price().await.ok(),
-                    },
+                    Some(ItemOrForma::Item(item)) => item_slot(item, prices, valuation).await,
                 }
             })
             .collect::<FuturesOrdered<_>>()
             .collect::<Vec<_>>()
             .await;
-        let _ = sender.send(Event::RewardsResolved(slots)).await;
+        let _ = sender.send(Event::RewardsResolved(slots.clone())).await;
+        // a set takes a lookup per part, the part prices don't wait for them
+        if fill_sets(&mut slots, db, prices).await {
+            let _ = sender.send(Event::RewardsResolved(slots.clone())).await;
+        }
 
         if finished {
             event!(Level::INFO, "relic screen run found all, finishing early");
@@ -94,9 +276,228 @@ pub async fn run_reward_session(
         }
     }
 
-    // keep the overlay up for the whole pick window even if OCR finished early
-    if let Some(rest) = session_duration.checked_sub(started.elapsed()) {
-        sleep(rest).await;
+    // whatever OCR got, even if some cards never resolved
+    if closed.is_none() {
+        if let Some((slot, reason)) = recommend(&slots, db, valuation) {
+            event!(Level::INFO, "recommending slot {slot}: {reason}");
+            let _ = sender.send(Event::Recommendation { slot, reason }).await;
+        }
+    }
+
+    // keep the overlay up for the rest of the pick window, unless the log
+    // says the screen closed or the cards OCR found can't be found anymore
+    let identified = total_results
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.is_some())
+        .map(|(i, _)| i)
+        .collect::<HashSet<_>>();
+    let mut gone_checks = 0;
+    while closed.is_none() && Instant::now() < deadline {
+        let next = (Instant::now() + LAYOUT_CHECK_INTERVAL).min(deadline);
+        if shut_down_before(&mut shut_down, next).await {
+            closed = Some(CloseReason::ShutDown);
+            continue;
+        }
+        if identified.is_empty() || Instant::now() >= deadline {
+            continue;
+        }
+        let Ok(image) = capture.capture() else {
+            continue;
+        };
+        let seen = parse_relic_screen(&image, &identified, &db.items).await;
+        gone_checks = match seen.iter().all(Option::is_none) {
+            true => gone_checks + 1,
+            false => {
+                last_seen = Some(image);
+                0
+            }
+        };
+        if gone_checks >= LAYOUT_GONE_CHECKS {
+            event!(Level::INFO, "reward cards gone from the screen");
+            closed = Some(CloseReason::CardsGone);
+        }
+    }
+    let Some(reason) = closed else {
+        return CloseReason::TimedOut;
+    };
+    let slot = last_seen.and_then(|image| highlighted_card(&image, count));
+    event!(Level::INFO, "reward picked: {slot:?}");
+    let _ = sender.send(Event::RewardPicked { slot }).await;
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use cephalon_rust_mock_market::MOCK_REQUESTS_PER_SECOND;
+
+    use crate::{
+        config::MarketClient,
+        items::{price::Median, price_cache::DEFAULT_PRICE_TTL},
+        test_support::mock_database,
+        valuation::BestUse,
+    };
+
+    use super::*;
+
+    struct NoWindow;
+
+    impl CaptureSource for NoWindow {
+        fn capture(&self) -> anyhow::Result<DynamicImage> {
+            anyhow::bail!("no window")
+        }
+    }
+
+    async fn session_events(
+        duration: Duration,
+        shut_down: oneshot::Receiver<()>,
+        cancel: SessionCancel,
+    ) -> Vec<Event> {
+        // nothing listens there, prices aren't needed without any cards
+        let market = MarketClient::new("http://127.0.0.1:9", MOCK_REQUESTS_PER_SECOND);
+        let prices = PriceCache::new(market, Arc::new(Median), DEFAULT_PRICE_TTL);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        run_reward_session(
+            &NoWindow,
+            &ItemDatabase::default(),
+            &prices,
+            &Valuation::default(),
+            &tx,
+            4,
+            None,
+            Vec::new(),
+            duration,
+            shut_down,
+            cancel,
+        )
+        .await;
+        drop(tx);
+        let mut events = Vec::new();
+        while let Some(e) = rx.recv().await {
+            events.push(e);
+        }
+        events
+    }
+
+    #[test]
+    fn shut_down_lines() {
+        let line = |content: &str| LogEntry::ScriptInfo {
+            script: "ProjectionRewardChoice".to_string(),
+            content: content.to_string(),
+        };
+        assert!(screen_shut_down(&line("Relic reward screen shut down")));
+        assert!(!screen_shut_down(&line("Relic rewards initialized")));
+    }
+
+    #[tokio::test]
+    async fn shut_down_ends_the_session_early() {
+        let (shut_down_sender, shut_down) = oneshot::channel();
+        shut_down_sender.send(()).unwrap();
+        let started = Instant::now();
+        let events =
+            session_events(Duration::from_secs(60), shut_down, SessionCancel::default()).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            events[1..],
+            [
+                Event::RewardPicked { slot: None },
+                Event::RewardScreenClosed {
+                    reason: CloseReason::ShutDown
+                }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_shut_down_runs_the_whole_window() {
+        let (shut_down_sender, shut_down) = oneshot::channel();
+        drop(shut_down_sender);
+        let started = Instant::now();
+        let events = session_events(
+            Duration::from_millis(1500),
+            shut_down,
+            SessionCancel::default(),
+        )
+        .await;
+        assert!(started.elapsed() >= Duration::from_millis(1500));
+        assert_eq!(
+            events.last(),
+            Some(&Event::RewardScreenClosed {
+                reason: CloseReason::TimedOut
+            })
+        );
+        assert!(!events
+            .iter()
+            .any(|x| matches!(x, Event::RewardPicked { .. })));
+    }
+
+    #[tokio::test]
+    async fn cancel_ends_the_session_with_its_reason() {
+        let (_shut_down_sender, shut_down) = oneshot::channel();
+        let cancel = SessionCancel::default();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            canceller.cancel(CloseReason::HostMigration);
+            // too late, the first reason stands
+            canceller.cancel(CloseReason::Disconnected);
+        });
+        let started = Instant::now();
+        let events = session_events(Duration::from_secs(60), shut_down, cancel).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            events.last(),
+            Some(&Event::RewardScreenClosed {
+                reason: CloseReason::HostMigration
+            })
+        );
+        assert!(!events
+            .iter()
+            .any(|x| matches!(x, Event::RewardPicked { .. } | Event::Recommendation { .. })));
+    }
+
+    #[tokio::test]
+    async fn set_context_sums_parts() {
+        let mock = mock_database().await;
+        let db = mock.database();
+        let prices = PriceCache::new(mock.market, Arc::new(Median), DEFAULT_PRICE_TTL);
+        let blade = db
+            .items
+            .values()
+            .find(|x| x.id_name == "okina_prime_blade")
+            .unwrap();
+
+        let set = set_context(blade, &db, &prices).await.unwrap();
+        assert_eq!(
+            set,
+            SetContext {
+                name: "Okina Prime Set".to_string(),
+                // no orders for the set itself or the other parts
+                price: Some(0),
+                parts_price: Some(4),
+                quantity_for_set: 2,
+            }
+        );
+
+        // no buy orders: worth more to baro than to anyone on the market
+        let mut slots = [
+            item_slot(blade, &prices, &Valuation::default()).await,
+            RewardSlot::Forma,
+        ];
+        assert!(matches!(
+            slots[0],
+            RewardSlot::Item {
+                set: None,
+                ducats: 25,
+                plat_per_ducat: Some(0.0),
+                best_use: Some(BestUse::Baro),
+                ..
+            }
+        ));
+        assert!(fill_sets(&mut slots, &db, &prices).await);
+        assert!(matches!(&slots[0], RewardSlot::Item { set: Some(x), .. } if *x == set));
+        assert_eq!(slots[1], RewardSlot::Forma);
     }
-    let _ = sender.send(Event::RewardScreenClosed).await;
 }
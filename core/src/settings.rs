@@ -0,0 +1,117 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use tracing::*;
+
+use crate::{
+    config::{MarketClient, DEFAULT_MARKET_URL, MARKET_REQUESTS_PER_SECOND},
+    items::{
+        price::{price_strategy_by_name, Median, PriceStrategy},
+        price_cache::{PriceCache, DEFAULT_PRICE_TTL},
+        snapshot::Snapshot,
+    },
+    recommendation::Objective,
+    valuation::{read_owned, Valuation, DEFAULT_PLAT_PER_DUCAT},
+};
+
+/// A setting that names something that doesn't exist.
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("PRICE_STRATEGY {0:?} names no built-in strategy")]
+    UnknownPriceStrategy(String),
+    #[error("OBJECTIVE {0:?} names no objective")]
+    UnknownObjective(String),
+}
+
+/// What every frontend configures the engine with, read from the environment
+/// (`CACHE_PATH`, `PRICE_STRATEGY`, ...).
+#[derive(Debug, Deserialize)]
+pub struct EngineSettings {
+    pub cache_path: PathBuf,
+    /// name of a built-in price strategy (see `price_strategy_by_name`); median when unset
+    #[serde(default)]
+    pub price_strategy: Option<String>,
+    /// seconds a fetched order book is reused; `DEFAULT_PRICE_TTL` when unset
+    #[serde(default)]
+    pub price_ttl: Option<u64>,
+    /// warframe.market-compatible api to talk to; the live site when unset
+    #[serde(default)]
+    pub market_url: Option<String>,
+    /// item snapshot to seed a cold cache with; the bundled one (if built
+    /// with `bundled-snapshot`) when unset
+    #[serde(default)]
+    pub snapshot_path: Option<PathBuf>,
+    /// local relic drop-data JSON (format on `DropTables`); no drop tables
+    /// when unset
+    #[serde(default)]
+    pub drop_data_path: Option<PathBuf>,
+    /// EE.log to watch; looked for in the usual steam, flatpak, lutris,
+    /// heroic and wine places when unset
+    #[serde(default)]
+    pub ee_log_path: Option<PathBuf>,
+    /// what a ducat is worth in platinum when weighing selling a part
+    /// against trading it to baro; `DEFAULT_PLAT_PER_DUCAT` when unset
+    #[serde(default)]
+    pub plat_per_ducat: Option<f64>,
+    /// what the recommended pick maximizes (see `Objective::by_name`); most
+    /// plat when unset
+    #[serde(default)]
+    pub objective: Option<String>,
+    /// JSON of part slugs to how many the player owns, for the objectives
+    /// that care; nothing owned when unset
+    #[serde(default)]
+    pub owned_path: Option<PathBuf>,
+}
+
+impl EngineSettings {
+    pub fn market(&self) -> MarketClient {
+        MarketClient::new(
+            self.market_url.as_deref().unwrap_or(DEFAULT_MARKET_URL),
+            MARKET_REQUESTS_PER_SECOND,
+        )
+    }
+
+    pub fn price_cache(&self, market: MarketClient) -> Result<PriceCache, SettingsError> {
+        let strategy: Arc<dyn PriceStrategy> = match &self.price_strategy {
+            Some(name) => price_strategy_by_name(name)
+                .ok_or_else(|| SettingsError::UnknownPriceStrategy(name.clone()))?,
+            None => Arc::new(Median),
+        };
+        let ttl = self
+            .price_ttl
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_PRICE_TTL);
+        Ok(PriceCache::new(market, strategy, ttl))
+    }
+
+    /// an unreadable owned-parts file is logged and counts as owning nothing
+    pub async fn valuation(&self) -> Result<Valuation, SettingsError> {
+        let objective = match &self.objective {
+            Some(name) => Objective::by_name(name)
+                .ok_or_else(|| SettingsError::UnknownObjective(name.clone()))?,
+            None => Objective::default(),
+        };
+        let owned = match &self.owned_path {
+            Some(path) => read_owned(path)
+                .await
+                .inspect_err(|e| event!(Level::WARN, "ignoring owned parts {path:?}: {e}"))
+                .unwrap_or_default(),
+            None => Default::default(),
+        };
+        Ok(Valuation {
+            plat_per_ducat: self.plat_per_ducat.unwrap_or(DEFAULT_PLAT_PER_DUCAT),
+            objective,
+            owned,
+        })
+    }
+
+    /// a snapshot that can't be read is logged and skipped, the engine can
+    /// still fetch everything itself
+    pub async fn snapshot(&self) -> Option<Snapshot> {
+        Snapshot::load(self.snapshot_path.as_deref())
+            .await
+            .inspect_err(|e| event!(Level::WARN, "ignoring item snapshot: {e}"))
+            .ok()
+            .flatten()
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use cephalon_rust_mock_market::{spawn, MOCK_REQUESTS_PER_SECOND};
+
+use crate::{
+    config::MarketClient,
+    items::{
+        item_identifiers::{get_item_identifiers, ItemIdentifier},
+        items::{fetch_items_and_sets, Item, ItemDatabase, ItemSet},
+    },
+};
+
+/// A freshly spawned mock market, and the items and sets built off it
+pub struct MockDatabase {
+    pub market: MarketClient,
+    pub identifiers: Vec<ItemIdentifier>,
+    pub items: HashMap<String, Item>,
+    pub sets: HashMap<String, ItemSet>,
+}
+
+impl MockDatabase {
+    /// the items and sets as a database, without relics or drop tables
+    pub fn database(&self) -> ItemDatabase {
+        ItemDatabase {
+            items: self.items.clone(),
+            sets: self.sets.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A client for a freshly spawned mock market
+pub async fn mock_market() -> MarketClient {
+    MarketClient::new(spawn().await, MOCK_REQUESTS_PER_SECOND)
+}
+
+/// Spawns a mock market and fetches every item and set it serves
+pub async fn mock_database() -> MockDatabase {
+    let market = mock_market().await;
+    let identifiers = get_item_identifiers(&market).await.unwrap().data;
+    let (items, sets) = fetch_items_and_sets(&market, &identifiers, &|_| {})
+        .await
+        .unwrap()
+        .data;
+    MockDatabase {
+        market,
+        identifiers,
+        items,
+        sets,
+    }
+}
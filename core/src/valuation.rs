@@ -0,0 +1,92 @@
+use std::{collections::HashMap, path::Path};
+
+use tokio::fs;
+
+use crate::recommendation::Objective;
+
+/// what a ducat is worth when nothing else is configured: 10 ducats to the
+/// plat, about what ducat-for-plat trades go for
+pub const DEFAULT_PLAT_PER_DUCAT: f64 = 0.1;
+
+/// Whether a part is worth more sold on the market or traded to Baro Ki'Teer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BestUse {
+    Sell,
+    Baro,
+}
+
+/// How rewards are weighed against each other beyond their market price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Valuation {
+    /// what one ducat is worth to the player, in platinum
+    pub plat_per_ducat: f64,
+    /// what the recommended pick maximizes
+    pub objective: Objective,
+    /// how many of each part the player has, keyed by slug; only the
+    /// objectives that care about owned parts look at it
+    pub owned: HashMap<String, u32>,
+}
+
+impl Default for Valuation {
+    fn default() -> Self {
+        Self {
+            plat_per_ducat: DEFAULT_PLAT_PER_DUCAT,
+            objective: Objective::default(),
+            owned: HashMap::new(),
+        }
+    }
+}
+
+impl Valuation {
+    /// selling wins ties, plat is easier to spend than ducats
+    pub fn best_use(&self, price: u32, ducats: u32) -> BestUse {
+        match (ducats as f64 * self.plat_per_ducat) > price as f64 {
+            true => BestUse::Baro,
+            false => BestUse::Sell,
+        }
+    }
+}
+
+/// platinum a part sells for per ducat it's worth; `None` for parts Baro
+/// doesn't take
+pub fn plat_per_ducat(price: u32, ducats: u32) -> Option<f64> {
+    match ducats {
+        0 => None,
+        _ => Some(price as f64 / ducats as f64),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OwnedError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("serde error")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// Read owned part counts from a JSON file of warframe.market slugs to
+/// counts, `{ "okina_prime_blade": 1 }`. Parts not in it are owned 0 times.
+pub async fn read_owned(path: &Path) -> Result<HashMap<String, u32>, OwnedError> {
+    Ok(serde_json::from_slice(&fs::read(path).await?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sell_or_baro() {
+        let valuation = Valuation::default();
+        assert_eq!(valuation.best_use(12, 100), BestUse::Sell);
+        assert_eq!(valuation.best_use(5, 100), BestUse::Baro);
+        assert_eq!(valuation.best_use(10, 100), BestUse::Sell);
+        assert_eq!(valuation.best_use(0, 0), BestUse::Sell);
+        let ducat_farmer = Valuation {
+            plat_per_ducat: 0.2,
+            ..Default::default()
+        };
+        assert_eq!(ducat_farmer.best_use(12, 100), BestUse::Baro);
+        assert_eq!(plat_per_ducat(12, 100), Some(0.12));
+        assert_eq!(plat_per_ducat(12, 0), None);
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use cephalon_rust_core::{
+    event::{CloseReason, EquippedRelic, Event},
+    game_state::{GameChange, GameState},
+    items::{items::ItemDatabase, relic_value::Refinement},
+    items::{price::Median, price_cache::PriceCache, price_cache::DEFAULT_PRICE_TTL},
+    log_watcher::replay::{Replay, ReplaySpeed},
+    test_support::mock_market,
+    valuation::Valuation,
+    Engine, ImageCapture,
+};
+use image::DynamicImage;
+
+const LOG: &str = "\
+10.000 Net [Info]: Num session players: 2
+10.100 Script [Info]: ProjectionManager.lua: Vaultrunner equipped Meso O4 Relic [Intact]
+10.200 Script [Info]: ProjectionManager.lua: equipped Lith B4 Relic [Radiant]
+10.300 Script [Info]: ProjectionManager.lua: Ordis equipped Neo N16 Relic [Flawless]
+11.000 Script [Info]: ProjectionRewardChoice.lua: Relic rewards initialized
+12.000 Script [Info]: ProjectionRewardChoice.lua: Relic reward screen shut down
+";
+
+/// every event an engine sends replaying `log`, with the database it used and
+/// the game state it ended up with
+async fn replay(log: &str) -> (Vec<Event>, Arc<ItemDatabase>, GameState) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("EE.log");
+    std::fs::write(&path, log).unwrap();
+    let market = mock_market().await;
+    let prices = PriceCache::new(market.clone(), Arc::new(Median), DEFAULT_PRICE_TTL);
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(1000);
+    let engine = Engine::new(
+        dir.path().join("cache"),
+        &market,
+        Arc::new(prices),
+        Valuation::default(),
+        None,
+        None,
+        &tx,
+    )
+    .await
+    .unwrap()
+    // nothing on screen, the log shutting the screen down ends the session
+    .with_capture(ImageCapture(DynamicImage::new_rgb8(1920, 1080)));
+    let db = engine.database();
+    let game = engine.game_state();
+
+    engine
+        .run_from(
+            tx,
+            Replay {
+                path,
+                speed: ReplaySpeed::Instant,
+            },
+        )
+        .await;
+
+    let mut events = Vec::new();
+    while let Some(e) = rx.recv().await {
+        events.push(e);
+    }
+    let game = game.borrow().clone();
+    (events, db, game)
+}
+
+#[tokio::test]
+async fn engine_runs_off_a_replay() {
+    let (events, db, game) = replay(LOG).await;
+    // logged, but not a relic the database knows
+    assert!(game.relics.iter().any(|x| x.name == "Neo N16"));
+    let events = events
+        .into_iter()
+        .filter(|e| {
+            !matches!(
+                e,
+                Event::ItemDatabaseProgress { .. }
+                    | Event::ItemDatabaseReady
+                    | Event::GameStateChanged(GameChange::Relic(_))
+            )
+        })
+        .collect::<Vec<_>>();
+    let relic = |name: &str, player: Option<&str>, refinement| EquippedRelic {
+        player: player.map(str::to_string),
+        relic: db.relic_by_name(name).unwrap().clone(),
+        refinement,
+    };
+    assert_eq!(
+        events,
+        [
+            Event::GameStateChanged(GameChange::SquadSize(2)),
+            Event::RewardScreenOpened {
+                count: 2,
+                window: None,
+                // the local player's first; an unknown relic is left out
+                relics: vec![
+                    relic("lith b4", None, Refinement::Radiant),
+                    relic("meso o4", Some("Vaultrunner"), Refinement::Intact),
+                ],
+            },
+            // the shut down beat the first capture, nothing to tell the
+            // pick by
+            Event::RewardPicked { slot: None },
+            Event::RewardScreenClosed {
+                reason: CloseReason::ShutDown
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn host_migration_cancels_the_session() {
+    let (events, _, _) = replay(
+        "\
+11.000 Script [Info]: ProjectionRewardChoice.lua: Relic rewards initialized
+11.200 Net [Info]: Num session players: 3
+11.500 Net [Info]: Host migration started
+",
+    )
+    .await;
+    // the squad shrinking on the way doesn't lose track of the session
+    assert!(events.contains(&Event::GameStateChanged(GameChange::SquadSize(3))));
+    assert!(events.contains(&Event::GameStateChanged(GameChange::HostMigration)));
+    // the engine and the session send concurrently, only the session's
+    // own order is fixed
+    let session = events
+        .into_iter()
+        .filter(|e| {
+            matches!(
+                e,
+                Event::RewardScreenOpened { .. }
+                    | Event::RewardPicked { .. }
+                    | Event::RewardScreenClosed { .. }
+            )
+        })
+        .collect::<Vec<_>>();
+    assert!(matches!(session[0], Event::RewardScreenOpened { .. }));
+    assert_eq!(
+        session[1..],
+        [Event::RewardScreenClosed {
+            reason: CloseReason::HostMigration
+        }]
+    );
+}
+
+#[tokio::test]
+async fn a_new_screen_closes_the_last_one() {
+    let (events, _, _) = replay(
+        "\
+11.000 Script [Info]: ProjectionRewardChoice.lua: Relic rewards initialized
+20.000 Script [Info]: ProjectionRewardChoice.lua: Relic rewards initialized
+21.000 Script [Info]: ProjectionRewardChoice.lua: Relic reward screen shut down
+",
+    )
+    .await;
+    let closed = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::RewardScreenClosed { reason } => Some(*reason),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events
+            .iter()
+            .filter(|e| matches!(e, Event::RewardScreenOpened { .. }))
+            .count(),
+        2
+    );
+    // the two sessions send concurrently, their closes can come either way
+    assert_eq!(closed.len(), 2);
+    assert!(closed.contains(&CloseReason::Replaced));
+    assert!(closed.contains(&CloseReason::ShutDown));
+    // the shut down was the second screen's, the first wasn't picked from
+    assert_eq!(
+        events
+            .iter()
+            .filter(|e| matches!(e, Event::RewardPicked { .. }))
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn a_failed_start_says_so() {
+    let dir = tempfile::tempdir().unwrap();
+    // a file where the cache directory should go
+    let cache_path = dir.path().join("cache");
+    std::fs::write(&cache_path, "").unwrap();
+    let market = mock_market().await;
+    let prices = PriceCache::new(market.clone(), Arc::new(Median), DEFAULT_PRICE_TTL);
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(1000);
+    let engine = Engine::new(
+        cache_path,
+        &market,
+        Arc::new(prices),
+        Valuation::default(),
+        None,
+        None,
+        &tx,
+    )
+    .await;
+    assert!(engine.is_err());
+    drop(tx);
+    assert!(matches!(
+        rx.recv().await,
+        Some(Event::ItemDatabaseFailed { .. })
+    ));
+}
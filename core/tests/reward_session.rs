@@ -1,10 +1,16 @@
-use std::{collections::HashMap, env, path::Path, time::Duration};
+use std::{sync::Arc, time::Duration};
 
 use cephalon_rust_core::{
-    event::{Event, RewardSlot},
+    event::{CloseReason, Event, RewardSlot},
     geometry::WindowRect,
-    items::{cached_get_item_identifiers, cached_items_and_sets, items::Item},
-    reward_session::{run_reward_session, CaptureSource},
+    items::{
+        price::Median,
+        price_cache::{PriceCache, DEFAULT_PRICE_TTL},
+    },
+    recommendation::Reason,
+    reward_session::{run_reward_session, CaptureSource, SessionCancel},
+    test_support::mock_database,
+    valuation::Valuation,
 };
 use image::{DynamicImage, ImageReader};
 
@@ -16,14 +22,6 @@ impl CaptureSource for StaticCapture {
     }
 }
 
-async fn items() -> HashMap<String, Item> {
-    let cache_path = env::var("CACHE_PATH").unwrap();
-    let cache_path = Path::new(&cache_path);
-    let identifiers = cached_get_item_identifiers(cache_path).await.unwrap();
-    let (items, _) = cached_items_and_sets(cache_path, &identifiers).await.unwrap();
-    items
-}
-
 #[tokio::test]
 async fn full_session_event_sequence() {
     let img = ImageReader::open("test_rewards_screens/1.png")
@@ -37,10 +35,27 @@ async fn full_session_event_sequence() {
         height: img.height(),
     };
     let capture = StaticCapture(img);
-    let items = items().await;
+    let mock = mock_database().await;
+    let db = mock.database();
+    let prices = PriceCache::new(mock.market, Arc::new(Median), DEFAULT_PRICE_TTL);
     let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(100);
+    // never shut down, the session runs its whole window
+    let (_shut_down_sender, shut_down) = tokio::sync::oneshot::channel();
 
-    run_reward_session(&capture, &items, &tx, 4, Some(window), Duration::from_secs(2)).await;
+    run_reward_session(
+        &capture,
+        &db,
+        &prices,
+        &Valuation::default(),
+        &tx,
+        4,
+        Some(window),
+        Vec::new(),
+        Duration::from_secs(2),
+        shut_down,
+        SessionCancel::default(),
+    )
+    .await;
     drop(tx);
 
     let mut events = Vec::new();
@@ -53,17 +68,23 @@ async fn full_session_event_sequence() {
         Some(&Event::RewardScreenOpened {
             count: 4,
             window: Some(window),
+            relics: Vec::new(),
+        })
+    );
+    assert_eq!(
+        events.last(),
+        Some(&Event::RewardScreenClosed {
+            reason: CloseReason::TimedOut
         })
     );
-    assert_eq!(events.last(), Some(&Event::RewardScreenClosed));
 
     let resolved = events
         .iter()
-        .filter_map(|e| match e {
+        .rev()
+        .find_map(|e| match e {
             Event::RewardsResolved(slots) => Some(slots),
             _ => None,
         })
-        .last()
         .expect("at least one RewardsResolved event");
 
     let names = resolved
@@ -71,16 +92,107 @@ async fn full_session_event_sequence() {
         .map(|s| match s {
             RewardSlot::Pending => "PENDING".to_string(),
             RewardSlot::Forma => "FORMA".to_string(),
-            RewardSlot::Item { item, .. } => item.name.clone(),
+            RewardSlot::Item {
+                item,
+                price: Some(p),
+                ..
+            } => format!("{} {p}p", item.name),
+            RewardSlot::Item {
+                item, price: None, ..
+            } => format!("{} ?p", item.name),
         })
         .collect::<Vec<_>>();
     assert_eq!(
         names,
         vec![
             "FORMA".to_string(),
-            "Okina Prime Handle".to_string(),
-            "Baruuk Prime Chassis Blueprint".to_string(),
-            "Shade Prime Systems".to_string(),
+            "Okina Prime Handle 4p".to_string(),
+            "Baruuk Prime Chassis Blueprint 11p".to_string(),
+            "Shade Prime Systems 4p".to_string(),
+        ]
+    );
+
+    // most plat by default
+    assert!(events.contains(&Event::Recommendation {
+        slot: 2,
+        reason: Reason::Plat(11),
+    }));
+
+    let okina = resolved
+        .iter()
+        .find_map(|s| match s {
+            RewardSlot::Item { item, set, .. } if item.id_name == "okina_prime_handle" => {
+                set.clone()
+            }
+            _ => None,
+        })
+        .expect("okina handle with its set");
+    assert_eq!(okina.name, "Okina Prime Set");
+    assert_eq!(okina.quantity_for_set, 1);
+    // only the handle has buy orders in the fixtures
+    assert_eq!(okina.parts_price, Some(4));
+
+    // its own price went out before the set's were in
+    let first_okina = events
+        .iter()
+        .find_map(|e| match e {
+            Event::RewardsResolved(slots) => slots.iter().find_map(|s| match s {
+                RewardSlot::Item { item, set, .. } if item.id_name == "okina_prime_handle" => {
+                    Some(set)
+                }
+                _ => None,
+            }),
+            _ => None,
+        })
+        .expect("okina handle resolved");
+    assert_eq!(*first_okina, None);
+}
+
+#[tokio::test]
+async fn shut_down_reports_the_selected_card() {
+    let img = ImageReader::open("test_rewards_screens/3.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let capture = StaticCapture(img);
+    let mock = mock_database().await;
+    let db = mock.database();
+    let prices = PriceCache::new(mock.market, Arc::new(Median), DEFAULT_PRICE_TTL);
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(100);
+    let (shut_down_sender, shut_down) = tokio::sync::oneshot::channel();
+    // after the first capture, the session's had a look at the cards
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let _ = shut_down_sender.send(());
+    });
+
+    run_reward_session(
+        &capture,
+        &db,
+        &prices,
+        &Valuation::default(),
+        &tx,
+        4,
+        None,
+        Vec::new(),
+        Duration::from_secs(60),
+        shut_down,
+        SessionCancel::default(),
+    )
+    .await;
+    drop(tx);
+
+    let mut events = Vec::new();
+    while let Some(e) = rx.recv().await {
+        events.push(e);
+    }
+    assert_eq!(
+        events[events.len() - 2..],
+        [
+            Event::RewardPicked { slot: Some(0) },
+            Event::RewardScreenClosed {
+                reason: CloseReason::ShutDown
+            }
         ]
     );
 }
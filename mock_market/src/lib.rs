@@ -0,0 +1,171 @@
+//! Stand-in for the parts of the warframe.market v2 api core uses, serving the
+//! JSON fixtures under `fixtures/`:
+//!
+//! - `items.json` — `/v2/items`
+//! - `sets/<set slug>.json` — `/v2/item/{slug}/set` for the set root and every part
+//! - `relics/<slug>.json` — `/v2/item/{slug}`
+//! - `orders/<slug>.json` — `/v2/orders/item/{slug}`; any other slug listed in
+//!   `items.json` has an empty order book
+//!
+//! Unknown slugs 404 like the real api.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+/// requests per second a `MarketClient` pointed at the mock can safely use;
+/// there's no upstream limit to respect, only test time to save
+pub const MOCK_REQUESTS_PER_SECOND: f64 = 1000.0;
+
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+struct Fixtures {
+    items: String,
+    /// every slug in `items.json`
+    known: HashSet<String>,
+    /// set json keyed by the slug of the root and of each part
+    sets: HashMap<String, Arc<str>>,
+    relics: HashMap<String, String>,
+    orders: HashMap<String, String>,
+}
+
+fn invalid(path: &Path, what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {what}", path.display()),
+    )
+}
+
+fn read_json(path: &Path) -> io::Result<(String, Value)> {
+    let raw = fs::read_to_string(path)?;
+    let value = serde_json::from_str(&raw).map_err(|e| invalid(path, &e.to_string()))?;
+    Ok((raw, value))
+}
+
+/// `<dir>/<slug>.json` files keyed by slug
+fn read_dir_by_stem(dir: &Path) -> io::Result<HashMap<String, String>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == "json") {
+            let slug = path.file_stem().unwrap().to_string_lossy().into_owned();
+            files.insert(slug, read_json(&path)?.0);
+        }
+    }
+    Ok(files)
+}
+
+impl Fixtures {
+    fn load(dir: &Path) -> io::Result<Self> {
+        let items_path = dir.join("items.json");
+        let (items, value) = read_json(&items_path)?;
+        let known = value["data"]
+            .as_array()
+            .ok_or_else(|| invalid(&items_path, "data is not a list"))?
+            .iter()
+            .filter_map(|x| x["slug"].as_str().map(str::to_string))
+            .collect();
+
+        let mut sets = HashMap::new();
+        for (_, raw) in read_dir_by_stem(&dir.join("sets"))? {
+            let value: Value = serde_json::from_str(&raw).unwrap();
+            let raw: Arc<str> = raw.into();
+            for slug in value["data"]["items"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|x| x["slug"].as_str())
+            {
+                sets.insert(slug.to_string(), raw.clone());
+            }
+        }
+
+        Ok(Self {
+            items,
+            known,
+            sets,
+            relics: read_dir_by_stem(&dir.join("relics"))?,
+            orders: read_dir_by_stem(&dir.join("orders"))?,
+        })
+    }
+}
+
+fn json(body: impl Into<String>) -> Response {
+    ([(CONTENT_TYPE, "application/json")], body.into()).into_response()
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        [(CONTENT_TYPE, "application/json")],
+        r#"{"apiVersion":"0.22.7","data":null,"error":{"request":["app.item.notFound"]}}"#,
+    )
+        .into_response()
+}
+
+async fn items(State(fixtures): State<Arc<Fixtures>>) -> Response {
+    json(fixtures.items.clone())
+}
+
+async fn item(State(fixtures): State<Arc<Fixtures>>, UrlPath(slug): UrlPath<String>) -> Response {
+    match fixtures.relics.get(&slug) {
+        Some(x) => json(x.clone()),
+        None => not_found(),
+    }
+}
+
+async fn set(State(fixtures): State<Arc<Fixtures>>, UrlPath(slug): UrlPath<String>) -> Response {
+    match fixtures.sets.get(&slug) {
+        Some(x) => json(x.to_string()),
+        None => not_found(),
+    }
+}
+
+async fn orders(State(fixtures): State<Arc<Fixtures>>, UrlPath(slug): UrlPath<String>) -> Response {
+    match fixtures.orders.get(&slug) {
+        Some(x) => json(x.clone()),
+        None if fixtures.known.contains(&slug) => {
+            json(r#"{"apiVersion":"0.22.7","data":[],"error":null}"#)
+        }
+        None => not_found(),
+    }
+}
+
+pub fn router(fixtures: &Path) -> io::Result<Router> {
+    Ok(Router::new()
+        .route("/v2/items", get(items))
+        .route("/v2/item/{slug}", get(item))
+        .route("/v2/item/{slug}/set", get(set))
+        .route("/v2/orders/item/{slug}", get(orders))
+        .with_state(Arc::new(Fixtures::load(fixtures)?)))
+}
+
+/// Serve the bundled fixtures on an ephemeral localhost port, on the current
+/// tokio runtime. Returns the base url to hand to `MarketClient::new`.
+pub async fn spawn() -> String {
+    spawn_fixtures(&fixtures_dir()).await
+}
+
+/// [`spawn`], serving the fixtures under `dir` instead, laid out the same
+pub async fn spawn_fixtures(dir: &Path) -> String {
+    let router = router(dir).expect("mock market fixtures");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{addr}")
+}
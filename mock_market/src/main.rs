@@ -0,0 +1,12 @@
+use std::env;
+
+use cephalon_rust_mock_market::{fixtures_dir, router};
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let addr = env::var("MOCK_MARKET_ADDR").unwrap_or_else(|_| "127.0.0.1:4000".to_string());
+    let listener = TcpListener::bind(&addr).await?;
+    println!("serving fixtures on http://{}", listener.local_addr()?);
+    axum::serve(listener, router(&fixtures_dir())?).await
+}
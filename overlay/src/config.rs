@@ -1,13 +1,22 @@
-use std::path::PathBuf;
+use cephalon_rust_core::settings::EngineSettings;
 
 use tokio::sync::OnceCell;
 
-#[derive(serde::Deserialize)]
 pub struct Settings {
-    pub cache_path: PathBuf,
+    pub engine: EngineSettings,
     /// index into display_info::DisplayInfo::all(); primary display when unset
-    #[serde(default)]
     pub monitor: Option<usize>,
+    /// show each part's ducats next to its price, "12p · 100d"
+    pub show_ducats: bool,
+}
+
+/// the settings only the overlay has, read from the same environment
+#[derive(serde::Deserialize)]
+struct OverlaySettings {
+    #[serde(default)]
+    monitor: Option<usize>,
+    #[serde(default)]
+    show_ducats: bool,
 }
 
 pub async fn settings() -> &'static Settings {
@@ -15,12 +24,16 @@ pub async fn settings() -> &'static Settings {
 
     (ONCE
         .get_or_init(|| async {
-            config::Config::builder()
+            let config = config::Config::builder()
                 .add_source(config::Environment::default())
                 .build()
-                .unwrap()
-                .try_deserialize()
-                .unwrap()
+                .unwrap();
+            let overlay: OverlaySettings = config.clone().try_deserialize().unwrap();
+            Settings {
+                engine: config.try_deserialize().unwrap(),
+                monitor: overlay.monitor,
+                show_ducats: overlay.show_ducats,
+            }
         })
         .await) as _
 }
@@ -1,10 +1,14 @@
 mod config;
 
-use std::fs::OpenOptions;
+use std::{fs::OpenOptions, sync::Arc, time::Duration};
 
 use cephalon_rust_core::{
-    event::{Event, RewardSlot},
+    config::MarketClient,
+    event::{Event, ItemDatabasePhase, RewardSlot},
     geometry::reward_card_regions,
+    items::price_cache::PriceCache,
+    settings::SettingsError,
+    valuation::{BestUse, Valuation},
     Engine,
 };
 use config::settings;
@@ -15,6 +19,9 @@ use winit::{
     window::WindowLevel,
 };
 
+/// how long the engine thread waits before trying `Engine::new` again
+const ENGINE_RETRY: Duration = Duration::from_secs(30);
+
 fn pick_display(monitor: Option<usize>) -> display_info::DisplayInfo {
     let displays = display_info::DisplayInfo::all().unwrap_or_default();
     monitor
@@ -39,11 +46,26 @@ fn main() {
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
     // settings() is async; resolve it on a throwaway runtime before the UI starts
-    let monitor = tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(async { settings().await.monitor });
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let setting = runtime.block_on(settings());
+    let show_ducats = setting.show_ducats;
+    // a setting naming nothing is reported now: once the hidden window is up,
+    // an engine that can't start would leave it behind as a zombie
+    let market = setting.engine.market();
+    let engine_settings = runtime.block_on(async {
+        let prices = setting.engine.price_cache(market.clone())?;
+        Ok::<_, SettingsError>((Arc::new(prices), setting.engine.valuation().await?))
+    });
+    let (prices, valuation) = match engine_settings {
+        Ok(x) => x,
+        Err(e) => {
+            tracing::error!("{e}");
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
 
-    let display = pick_display(monitor);
+    let display = pick_display(setting.monitor);
     // +1/-1 px fudge: transparent windows at exact monitor size go black (Orbolay's workaround)
     let size = PhysicalSize::new(
         (display.width + 1) as f64 * display.scale_factor as f64,
@@ -52,10 +74,13 @@ fn main() {
     let position = PhysicalPosition::new(display.x, display.y);
     // freya lays out in logical points; geometry needs the same space
     let logical = (display.width + 1, display.height - 1);
+    let origin = (display.x, display.y);
+    // what the engine starts with, validated above
+    let init = (market, prices, valuation);
 
     launch(
         LaunchConfig::new().with_window(
-            WindowConfig::new(move || app(logical.0, logical.1, (display.x, display.y)))
+            WindowConfig::new(move || app(logical.0, logical.1, origin, show_ducats, init.clone()))
                 .with_title("cephalon")
                 .with_decorations(false)
                 .with_transparency(true)
@@ -80,8 +105,16 @@ fn main() {
     );
 }
 
-fn app(width: u32, height: u32, display_origin: (i32, i32)) -> impl IntoElement {
+fn app(
+    width: u32,
+    height: u32,
+    display_origin: (i32, i32),
+    show_ducats: bool,
+    (market, prices, valuation): (MarketClient, Arc<PriceCache>, Valuation),
+) -> impl IntoElement {
     let mut screen = use_state(|| Option::<RewardScreen>::None);
+    // "building item database" banner text while a cold-cache fetch runs
+    let mut building = use_state(|| Option::<String>::None);
 
     use_hook(move || {
         Platform::get().with_window(None, |w| {
@@ -93,20 +126,31 @@ fn app(width: u32, height: u32, display_origin: (i32, i32)) -> impl IntoElement
         // engine lives on its own tokio runtime; the UI runtime is not tokio
         std::thread::spawn(move || {
             tokio::runtime::Runtime::new().unwrap().block_on(async move {
-                let settings = settings().await;
+                let settings = &settings().await.engine;
+                let snapshot = settings.snapshot().await;
                 // cold-cache init fires hundreds of live-market requests; transient
                 // failure is realistic. retry rather than silently killing the thread
                 // and leaving the hidden window as a zombie.
                 let engine = loop {
-                    match Engine::new(settings.cache_path.clone()).await {
+                    match Engine::new(
+                        settings.cache_path.clone(),
+                        &market,
+                        prices.clone(),
+                        valuation.clone(),
+                        snapshot.as_ref(),
+                        settings.drop_data_path.as_deref(),
+                        &tx,
+                    )
+                    .await
+                    {
                         Ok(engine) => break engine,
                         Err(e) => {
-                            tracing::error!("engine init failed, retrying in 30s: {e}");
-                            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+                            tracing::error!("engine init failed, retrying: {e}");
+                            tokio::time::sleep(ENGINE_RETRY).await;
                         }
                     }
                 };
-                engine.run(tx).await;
+                engine.run(tx, settings.ee_log_path.clone()).await;
             });
         });
 
@@ -114,10 +158,37 @@ fn app(width: u32, height: u32, display_origin: (i32, i32)) -> impl IntoElement
         spawn_forever(async move {
             while let Some(event) = rx.recv().await {
                 match event {
-                    Event::RewardScreenOpened { count, window } => {
+                    Event::ItemDatabaseProgress {
+                        phase: ItemDatabasePhase::ItemList,
+                        ..
+                    } => {
+                        building.set(Some("building item database".to_string()));
+                    }
+                    Event::ItemDatabaseProgress {
+                        phase: phase @ (ItemDatabasePhase::ItemSets | ItemDatabasePhase::Relics),
+                        progress,
+                        ..
+                    } => {
+                        building.set(Some(format!(
+                            "building item database: {phase} {}/{}",
+                            progress.done, progress.total
+                        )));
+                    }
+                    Event::ItemDatabaseReady => {
+                        building.set(None);
+                    }
+                    // whatever progress the banner showed isn't coming along
+                    Event::ItemDatabaseFailed { error } => {
+                        building.set(Some(format!(
+                            "building item database failed, retrying in {}s: {error}",
+                            ENGINE_RETRY.as_secs()
+                        )));
+                    }
+                    Event::RewardScreenOpened { count, window, .. } => {
                         screen.set(Some(RewardScreen {
                             slots: vec![RewardSlot::Pending; count],
                             window,
+                            recommended: None,
                         }));
                     }
                     Event::RewardsResolved(resolved) => {
@@ -129,25 +200,47 @@ fn app(width: u32, height: u32, display_origin: (i32, i32)) -> impl IntoElement
                         // non-subscribing, correct here — this is a bridge task, not render.
                         let window = screen.peek().as_ref().map(|s| s.window);
                         if let Some(window) = window {
-                            screen.set(Some(RewardScreen { slots: resolved, window }));
+                            screen.set(Some(RewardScreen {
+                                slots: resolved,
+                                window,
+                                recommended: None,
+                            }));
+                        }
+                    }
+                    Event::Recommendation { slot, .. } => {
+                        // same peek-then-set dance as Resolved
+                        let current = screen.peek().clone();
+                        if let Some(current) = current {
+                            screen.set(Some(RewardScreen {
+                                recommended: Some(slot),
+                                ..current
+                            }));
                         }
                     }
-                    Event::RewardScreenClosed => {
+                    // the pick is made, the labels would only cover the game
+                    Event::RewardPicked { .. } | Event::RewardScreenClosed { .. } => {
+                        screen.set(None);
+                    }
+                    // a session still running belongs to the old game; its
+                    // Closed still comes, but there's no point showing it
+                    Event::GameRestarted => {
                         screen.set(None);
                     }
+                    Event::GameStateChanged(_) => {}
                 }
             }
         });
     });
 
     use_side_effect(move || {
-        let visible = screen.read().is_some();
+        let visible = screen.read().is_some() || building.read().is_some();
         Platform::get().with_window(None, move |w| w.set_visible(visible));
     });
 
     rect()
         .width(Size::fill())
         .height(Size::fill())
+        .maybe_child(building.read().clone().map(|text| Banner { text }))
         .maybe_child(screen.read().clone().map(|s| {
             // labels live inside the GAME WINDOW's rect, not the monitor's:
             // warframe may be borderless on half an ultrawide or another monitor.
@@ -165,14 +258,46 @@ fn app(width: u32, height: u32, display_origin: (i32, i32)) -> impl IntoElement
                 offset_y: game.y - display_origin.1,
                 width: game.width,
                 height: game.height,
+                show_ducats,
+                recommended: s.recommended,
             }
         }))
 }
 
+/// small status pill at the top of the overlay window
+#[derive(PartialEq)]
+struct Banner {
+    text: String,
+}
+
+impl Component for Banner {
+    fn render(&self) -> impl IntoElement {
+        rect()
+            .position(Position::new_absolute().top(24.).left(0.))
+            .width(Size::fill())
+            .direction(Direction::Horizontal)
+            .main_align(Alignment::Center)
+            .child(
+                rect()
+                    .padding(Gaps::new(4., 14., 4., 14.))
+                    .corner_radius(CornerRadius::new_all(12.))
+                    .background(Color::new(0xCC14141A))
+                    .child(
+                        label()
+                            .font_size(18.)
+                            .color(Color::WHITE)
+                            .text(self.text.clone()),
+                    ),
+            )
+    }
+}
+
 #[derive(PartialEq, Clone)]
 struct RewardScreen {
     slots: Vec<RewardSlot>,
     window: Option<cephalon_rust_core::geometry::WindowRect>,
+    /// slot index from `Event::Recommendation`
+    recommended: Option<usize>,
 }
 
 #[derive(PartialEq)]
@@ -184,12 +309,16 @@ struct RewardLabels {
     /// game window size — geometry is computed in the game's pixel space
     width: u32,
     height: u32,
+    /// "12p · 100d" instead of "12p"
+    show_ducats: bool,
+    /// slot to draw the highlight border around
+    recommended: Option<usize>,
 }
 
 impl Component for RewardLabels {
     fn render(&self) -> impl IntoElement {
         let regions = reward_card_regions(self.width, self.height, self.slots.len());
-        self.slots.iter().zip(regions).fold(
+        self.slots.iter().zip(regions).enumerate().fold(
             rect()
                 .position(
                     Position::new_absolute()
@@ -198,13 +327,43 @@ impl Component for RewardLabels {
                 )
                 .width(Size::fill())
                 .height(Size::fill()),
-            |el, (slot, region)| {
+            |el, (i, (slot, region))| {
                 let text = match slot {
                     RewardSlot::Pending => "…".to_string(),
                     RewardSlot::Forma => "—".to_string(),
-                    RewardSlot::Item { price: Some(p), .. } => format!("{p}p"),
-                    RewardSlot::Item { price: None, .. } => "?".to_string(),
+                    RewardSlot::Item { price, ducats, .. } => {
+                        let price = price.map_or("?".to_string(), |p| format!("{p}p"));
+                        match self.show_ducats {
+                            true => format!("{price} · {ducats}d"),
+                            false => price,
+                        }
+                    }
+                };
+                // baro gold when the ducats are worth more than the plat
+                let color = match slot {
+                    RewardSlot::Item {
+                        best_use: Some(BestUse::Baro),
+                        ..
+                    } if self.show_ducats => Color::new(0xFFE8C46A),
+                    _ => Color::WHITE,
                 };
+                // second, smaller line: what the part's set goes for, to weigh
+                // keeping it against selling it loose
+                let set_text = match slot {
+                    RewardSlot::Item { set: Some(set), .. } => {
+                        set.price.map(|s| match set.parts_price {
+                            Some(parts) => format!("set {s}p · parts {parts}p"),
+                            None => format!("set {s}p"),
+                        })
+                    }
+                    _ => None,
+                };
+                let border = (self.recommended == Some(i)).then(|| {
+                    Border::new()
+                        .fill(Color::new(0xFF6AD0E8))
+                        .width(3.)
+                        .alignment(BorderAlignment::Outer)
+                });
                 el.child(
                     rect()
                         .position(
@@ -220,13 +379,20 @@ impl Component for RewardLabels {
                                 .padding(Gaps::new(4., 14., 4., 14.))
                                 .corner_radius(CornerRadius::new_all(12.))
                                 .background(Color::new(0xCC14141A))
+                                .border(border)
                                 .child(
                                     label()
                                         .font_size(26.)
                                         .font_weight(FontWeight::BOLD)
-                                        .color(Color::WHITE)
+                                        .color(color)
                                         .text(text),
-                                ),
+                                )
+                                .maybe_child(set_text.map(|text| {
+                                    label()
+                                        .font_size(16.)
+                                        .color(Color::new(0xFFC8C8D0))
+                                        .text(text)
+                                })),
                         ),
                 )
             },